@@ -0,0 +1,143 @@
+use convolution::crossfade_convolver::CrossfadeConvolver;
+use convolution::fft_convolver::{FFTConvolver, TwoStageFFTConvolver};
+use convolution::frequency_domain_crossfade_convolver::FrequencyDomainCrossfadeConvolver;
+use convolution::split_complex::SplitSpectra;
+use convolution::Convolution;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rustfft::num_complex::Complex;
+
+const SAMPLE_RATE: usize = 48000;
+const BLOCK_SIZE: usize = 256;
+
+fn generate_noise(length: usize) -> Vec<f32> {
+    // Deterministic pseudo random signal, the exact content does not matter for the runtime
+    let mut state = 0x1234_5678u32;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32 - 0.5
+        })
+        .collect()
+}
+
+fn bench_process<C: Convolution>(c: &mut Criterion, name: &str) {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(BLOCK_SIZE as u64));
+    let input = generate_noise(BLOCK_SIZE);
+    let mut output = vec![0.0; BLOCK_SIZE];
+
+    for seconds in [1, 5, 10] {
+        let response = generate_noise(seconds * SAMPLE_RATE);
+        let mut convolver = C::init(&response, BLOCK_SIZE, response.len());
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{seconds}s")),
+            &seconds,
+            |b, _| b.iter(|| convolver.process(&input, &mut output)),
+        );
+    }
+    group.finish();
+}
+
+fn fft_convolver(c: &mut Criterion) {
+    bench_process::<FFTConvolver>(c, "FFTConvolver");
+}
+
+fn two_stage_fft_convolver(c: &mut Criterion) {
+    bench_process::<TwoStageFFTConvolver>(c, "TwoStageFFTConvolver");
+}
+
+// Outside of a crossfade
+fn crossfade_convolver(c: &mut Criterion) {
+    bench_process::<CrossfadeConvolver<FFTConvolver>>(c, "CrossfadeConvolver");
+}
+
+fn frequency_domain_crossfade_convolver(c: &mut Criterion) {
+    bench_process::<FrequencyDomainCrossfadeConvolver>(c, "FrequencyDomainCrossfadeConvolver");
+}
+
+fn generate_spectra(count: usize, bins: usize) -> Vec<Vec<Complex<f32>>> {
+    let noise = generate_noise(2 * count * bins);
+    noise
+        .chunks(2 * bins)
+        .map(|spectrum| {
+            spectrum
+                .chunks(2)
+                .map(|bin| Complex::new(bin[0], bin[1]))
+                .collect()
+        })
+        .collect()
+}
+
+// The multiplication of all partitions with the input segments, which dominates the runtime
+// of long responses. The previous layout of one interleaved vector per spectrum is the baseline
+// for the contiguous split-complex arena. Both are multiplied in the same plain loop, so that
+// the comparison shows the layout alone and not the vectorized kernels.
+fn partition_layout(c: &mut Criterion) {
+    let mut group = c.benchmark_group("PartitionLayout");
+    group.throughput(Throughput::Elements(BLOCK_SIZE as u64));
+    let bins = BLOCK_SIZE + 1;
+
+    for seconds in [1, 5, 10] {
+        let count = (seconds * SAMPLE_RATE + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        let segments = generate_spectra(count, bins);
+        let partitions = generate_spectra(count, bins);
+
+        let mut result = vec![Complex::new(0.0, 0.0); bins];
+        group.bench_with_input(
+            BenchmarkId::new("Vec<Vec<Complex>>", format!("{seconds}s")),
+            &seconds,
+            |b, _| {
+                b.iter(|| {
+                    for (segment, partition) in segments.iter().zip(&partitions) {
+                        for ((result, a), b) in result.iter_mut().zip(segment).zip(partition) {
+                            *result += a * b;
+                        }
+                    }
+                    black_box(&mut result);
+                })
+            },
+        );
+
+        let mut split_segments = SplitSpectra::new(count, bins);
+        let mut split_partitions = SplitSpectra::new(count, bins);
+        for (index, (segment, partition)) in segments.iter().zip(&partitions).enumerate() {
+            split_segments.store(index, segment);
+            split_partitions.store(index, partition);
+        }
+        let mut split_result = SplitSpectra::<f32>::new(1, bins);
+        group.bench_with_input(
+            BenchmarkId::new("SplitSpectra", format!("{seconds}s")),
+            &seconds,
+            |b, _| {
+                b.iter(|| {
+                    let result = split_result.get_mut(0);
+                    for index in 0..count {
+                        let a = split_segments.get(index);
+                        let b = split_partitions.get(index);
+                        let result = result.re.iter_mut().zip(result.im.iter_mut());
+                        let a = a.re.iter().zip(a.im);
+                        let b = b.re.iter().zip(b.im);
+                        for (((re, im), (a_re, a_im)), (b_re, b_im)) in result.zip(a).zip(b) {
+                            *re += a_re * b_re - a_im * b_im;
+                            *im += a_re * b_im + a_im * b_re;
+                        }
+                    }
+                    black_box(&mut split_result);
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    partition_layout,
+    fft_convolver,
+    two_stage_fft_convolver,
+    crossfade_convolver,
+    frequency_domain_crossfade_convolver
+);
+criterion_main!(benches);
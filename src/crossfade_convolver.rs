@@ -1,108 +1,624 @@
-use crate::{Convolution, Sample};
+use std::collections::VecDeque;
+
+use crate::{Convolution, ConvolutionError, Sample};
 
 #[derive(Clone)]
-struct CrossfadeConvolverCore<T: Convolution> {
+struct CrossfadeConvolverCore<T: Convolution<S>, S: Sample, M: Mixer> {
     convolver_a: T,
     convolver_b: T,
-    crossfader: Crossfader<RaisedCosineMixer>,
+    crossfader: Crossfader<M, S>,
 }
 
+/// Switches between responses by crossfading between two convolvers, the shape of the
+/// crossfade is defined by the `Mixer`.
 #[derive(Clone)]
-pub struct CrossfadeConvolver<Convolver: Convolution> {
-    core: CrossfadeConvolverCore<Convolver>,
-    buffer_a: Vec<Sample>,
-    buffer_b: Vec<Sample>,
-    stored_response: Vec<Sample>,
-    response_pending: bool,
+pub struct CrossfadeConvolver<
+    Convolver: Convolution<S>,
+    S: Sample = f32,
+    M: Mixer = RaisedCosineMixer,
+> {
+    core: CrossfadeConvolverCore<Convolver, S, M>,
+    buffer_a: Vec<S>,
+    buffer_b: Vec<S>,
+    max_response_length: usize,
+    // Responses waiting for the running crossfade to complete, one slot of
+    // `max_response_length` samples per queued update
+    stored_responses: Vec<S>,
+    pending: VecDeque<PendingUpdate>,
+    pending_capacity: usize,
+    policy: PendingUpdatePolicy,
+    events: VecDeque<UpdateEvent>,
+    // Updates held by convolver A and B
+    update_ids: [UpdateId; 2],
+    next_update_id: u64,
+}
+
+/// Identifies an update of a `CrossfadeConvolver`, see `CrossfadeConvolver::try_queue_update`.
+/// The response the convolver was created with has the first id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UpdateId(u64);
+
+/// What happens to updates that arrive while a crossfade is running.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PendingUpdatePolicy {
+    /// A new update replaces all pending ones, which are dropped.
+    #[default]
+    LatestWins,
+    /// Updates are faded to one after the other, if the queue is full the update fails with
+    /// `ConvolutionError::QueueFull`.
+    QueueAll,
+    /// If the queue is full, the new update is dropped.
+    DropNew,
 }
 
-impl<T: Convolution> CrossfadeConvolver<T> {
+/// Progress of an update, see `CrossfadeConvolver::pop_update_event`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateEvent {
+    /// The crossfade to the response started, beginning with the hold period.
+    Started(UpdateId),
+    /// The crossfade to the response is complete, only the response is audible now.
+    Finished(UpdateId),
+    /// The response was never faded to, because of the `PendingUpdatePolicy` or an error.
+    Dropped(UpdateId),
+}
+
+/// State of an update, see `CrossfadeConvolver::update_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateStatus {
+    /// Waiting for the running crossfade to complete.
+    Pending,
+    /// Being crossfaded to.
+    FadingIn,
+    /// Being crossfaded from.
+    FadingOut,
+    /// The current response.
+    Active,
+    /// Replaced by a later update or dropped.
+    Inactive,
+}
+
+#[derive(Clone, Copy)]
+struct PendingUpdate {
+    id: UpdateId,
+    slot: usize,
+    len: usize,
+}
+
+impl<T: Convolution<S>, S: Sample> CrossfadeConvolver<T, S> {
+    /// Crossfades between two copies of `convolver` over `crossfade_samples` samples with a
+    /// `RaisedCosineMixer`, see `with_mixer`.
     pub fn new(
         convolver: T,
         max_response_length: usize,
         max_buffer_size: usize,
         crossfade_samples: usize,
     ) -> Self {
-        let stored_response = vec![0.0; max_response_length];
-        Self {
+        Self::with_mixer(
+            convolver,
+            max_response_length,
+            max_buffer_size,
+            crossfade_samples,
+            RaisedCosineMixer,
+        )
+    }
+}
+
+impl<T: Convolution<S>, S: Sample, M: Mixer> CrossfadeConvolver<T, S, M> {
+    /// Crossfades between two copies of `convolver` over `crossfade_samples` samples.
+    ///
+    /// Outside of a crossfade only the convolver with the current response is processed, the
+    /// idle one is merely fed the input (see `Convolution::try_feed`) to keep its history. An
+    /// update lets the idle convolver run alongside for a hold period of `max_buffer_size`
+    /// samples (at most `max_response_length`) before the new response fades in. Convolvers
+    /// that drop more than one block of output on an update, e.g. the tail stages of a
+    /// `TwoStageFFTConvolver`, need a longer hold period to fade in exactly, which can be
+    /// chosen with a `CrossfadeConvolverBuilder`.
+    ///
+    /// Larger buffers passed to `process` are processed in chunks of `max_buffer_size` samples.
+    pub fn with_mixer(
+        convolver: T,
+        max_response_length: usize,
+        max_buffer_size: usize,
+        crossfade_samples: usize,
+        mixer: M,
+    ) -> Self {
+        Self::from_parts(
+            convolver,
+            max_response_length,
+            max_buffer_size,
+            crossfade_samples,
+            max_buffer_size.min(max_response_length),
+            mixer,
+        )
+    }
+
+    fn from_parts(
+        convolver: T,
+        max_response_length: usize,
+        max_buffer_size: usize,
+        crossfade_samples: usize,
+        hold_samples: usize,
+        mixer: M,
+    ) -> Self {
+        let mut convolver = Self {
             core: CrossfadeConvolverCore {
                 convolver_a: convolver.clone(),
                 convolver_b: convolver,
-                crossfader: Crossfader::new(
-                    RaisedCosineMixer,
-                    crossfade_samples,
-                    max_buffer_size.min(max_response_length),
-                ),
+                crossfader: Crossfader::new(mixer, crossfade_samples, hold_samples),
             },
-            buffer_a: vec![0.0; max_buffer_size],
-            buffer_b: vec![0.0; max_buffer_size],
-            stored_response,
-            response_pending: false,
+            buffer_a: vec![S::zero(); max_buffer_size.max(1)],
+            buffer_b: vec![S::zero(); max_buffer_size.max(1)],
+            max_response_length,
+            stored_responses: Vec::new(),
+            pending: VecDeque::new(),
+            pending_capacity: 0,
+            policy: PendingUpdatePolicy::LatestWins,
+            events: VecDeque::new(),
+            update_ids: [UpdateId(0); 2],
+            next_update_id: 1,
+        };
+        convolver.set_pending_updates(1, PendingUpdatePolicy::LatestWins);
+        convolver
+    }
+
+    /// Makes space for `capacity` (at least one) updates waiting for a running crossfade and
+    /// selects what happens to further updates. Drops the pending updates, this allocates and
+    /// is not real-time safe.
+    pub fn set_pending_updates(&mut self, capacity: usize, policy: PendingUpdatePolicy) {
+        let capacity = capacity.max(1);
+        self.drop_pending_updates();
+        self.stored_responses = vec![S::zero(); capacity * self.max_response_length];
+        self.pending = VecDeque::with_capacity(capacity);
+        self.pending_capacity = capacity;
+        self.policy = policy;
+        // Every update causes two events at most, this keeps the events of the running and the
+        // pending updates twice over, for callers polling once per block
+        self.events = VecDeque::with_capacity(4 * (capacity + 1));
+    }
+
+    /// Crossfades to `response`, or if a crossfade is running, queues the response according
+    /// to the `PendingUpdatePolicy`. Returns the id of the update to follow its progress with
+    /// `update_status` or `pop_update_event`.
+    pub fn try_queue_update(&mut self, response: &[S]) -> Result<UpdateId, ConvolutionError> {
+        if response.len() > self.max_response_length {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.max_response_length,
+            });
+        }
+
+        if !self.is_crossfading() && self.pending.is_empty() {
+            swap(&mut self.core, response)?;
+            let id = self.next_update_id();
+            self.started(id);
+            return Ok(id);
         }
+
+        let full = self.pending.len() == self.pending_capacity;
+        match self.policy {
+            PendingUpdatePolicy::LatestWins => self.drop_pending_updates(),
+            PendingUpdatePolicy::QueueAll if full => return Err(ConvolutionError::QueueFull),
+            PendingUpdatePolicy::DropNew if full => {
+                let id = self.next_update_id();
+                self.push_event(UpdateEvent::Dropped(id));
+                return Ok(id);
+            }
+            PendingUpdatePolicy::QueueAll | PendingUpdatePolicy::DropNew => {}
+        }
+
+        let slot = (0..self.pending_capacity)
+            .find(|slot| self.pending.iter().all(|update| update.slot != *slot))
+            .unwrap();
+        let start = slot * self.max_response_length;
+        self.stored_responses[start..start + response.len()].copy_from_slice(response);
+        let id = self.next_update_id();
+        self.pending.push_back(PendingUpdate {
+            id,
+            slot,
+            len: response.len(),
+        });
+        Ok(id)
     }
-}
 
-impl<Convolver: Convolution> Convolution for CrossfadeConvolver<Convolver> {
-    fn init(response: &[Sample], max_block_size: usize, max_response_length: usize) -> Self {
-        let convolver = Convolver::init(response, max_block_size, max_response_length);
-        Self::new(convolver, response.len(), max_block_size, response.len())
+    /// Like `try_queue_update`, but panics on invalid arguments or if the queue is full.
+    pub fn queue_update(&mut self, response: &[S]) -> UpdateId {
+        self.try_queue_update(response)
+            .unwrap_or_else(|error| panic!("{error}"))
     }
 
-    fn update(&mut self, response: &[Sample]) {
-        if !self.is_crossfading() {
-            swap(&mut self.core, response);
-            self.response_pending = false;
-            return;
+    /// Polls the progress of the update `id`, see `pop_update_event` for notifications.
+    pub fn update_status(&self, id: UpdateId) -> UpdateStatus {
+        let target = self.core.crossfader.target();
+        if self.pending.iter().any(|update| update.id == id) {
+            UpdateStatus::Pending
+        } else if id == self.update_ids[target as usize] {
+            if self.is_crossfading() {
+                UpdateStatus::FadingIn
+            } else {
+                UpdateStatus::Active
+            }
+        } else if id == self.update_ids[target.other() as usize] && self.is_crossfading() {
+            UpdateStatus::FadingOut
+        } else {
+            UpdateStatus::Inactive
         }
+    }
+
+    /// Takes the oldest event of the updates, real-time safe. Only the most recent events are
+    /// kept if they are not taken.
+    pub fn pop_update_event(&mut self) -> Option<UpdateEvent> {
+        self.events.pop_front()
+    }
 
-        let response_len = response.len();
-        assert!(response_len <= self.stored_response.len());
+    fn next_update_id(&mut self) -> UpdateId {
+        let id = UpdateId(self.next_update_id);
+        self.next_update_id += 1;
+        id
+    }
 
-        self.stored_response[..response_len].copy_from_slice(response);
-        self.stored_response[response_len..].fill(0.0);
-        self.response_pending = true;
+    // Called after `swap` started fading to the response of the update
+    fn started(&mut self, id: UpdateId) {
+        self.update_ids[self.core.crossfader.target() as usize] = id;
+        self.push_event(UpdateEvent::Started(id));
     }
 
-    fn process(&mut self, input: &[Sample], output: &mut [Sample]) {
-        if !self.is_crossfading() && self.response_pending {
-            swap(&mut self.core, &mut self.stored_response);
-            self.response_pending = false;
+    fn drop_pending_updates(&mut self) {
+        while let Some(update) = self.pending.pop_front() {
+            self.push_event(UpdateEvent::Dropped(update.id));
         }
+    }
 
-        self.core.convolver_a.process(input, &mut self.buffer_a);
-        self.core.convolver_b.process(input, &mut self.buffer_b);
+    fn push_event(&mut self, event: UpdateEvent) {
+        if self.events.len() == self.events.capacity() {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+}
+
+impl<Convolver: Convolution<S>, S: Sample, M: Mixer> Convolution<S>
+    for CrossfadeConvolver<Convolver, S, M>
+{
+    /// Uses the defaults of the `CrossfadeConvolverBuilder`.
+    fn try_init(
+        response: &[S],
+        max_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        CrossfadeConvolverBuilder::new(max_block_size, max_response_length)
+            .mixer(M::default())
+            .try_build(response)
+    }
 
-        for i in 0..output.len() {
-            output[i] = self.core.crossfader.mix(self.buffer_a[i], self.buffer_b[i]);
+    /// See `try_queue_update`.
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        self.try_queue_update(response).map(|_| ())
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if input.len() != output.len() {
+            return Err(ConvolutionError::BufferSizeMismatch {
+                expected: output.len(),
+                actual: input.len(),
+            });
+        }
+
+        // Buffers larger than the maximum buffer size are processed in chunks, each of them
+        // like a separate call
+        let chunk_size = self.buffer_a.len();
+        let mut result = Ok(());
+        for (input, output) in input.chunks(chunk_size).zip(output.chunks_mut(chunk_size)) {
+            result = result.and(self.process_chunk(input, output));
+        }
+        result
+    }
+
+    /// Also completes a running crossfade, a pending response becomes active with the next
+    /// processed block.
+    fn reset(&mut self) {
+        let was_crossfading = self.is_crossfading();
+        self.core.convolver_a.reset();
+        self.core.convolver_b.reset();
+        self.core.crossfader.reset();
+        if was_crossfading {
+            self.finished();
+        }
+    }
+
+    /// The signal is not delayed, but a new response only starts fading in after the hold
+    /// period, see `CrossfadeConvolver::with_mixer`.
+    fn latency(&self) -> usize {
+        self.core.convolver_a.latency()
+    }
+
+    fn block_size(&self) -> usize {
+        self.core.convolver_a.block_size()
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.max_response_length
+            .min(self.core.convolver_a.max_response_length())
+    }
+
+    /// Length of the response that is faded to, or was faded to last.
+    fn response_length(&self) -> usize {
+        self.target_convolver().response_length()
+    }
+
+    /// While crossfading, the longer tail of both responses.
+    fn tail_length(&self) -> usize {
+        if self.is_crossfading() {
+            self.core
+                .convolver_a
+                .tail_length()
+                .max(self.core.convolver_b.tail_length())
+        } else {
+            self.target_convolver().tail_length()
         }
     }
 }
 
-impl<Convolver: Convolution> CrossfadeConvolver<Convolver> {
+impl<Convolver: Convolution<S>, S: Sample, M: Mixer> CrossfadeConvolver<Convolver, S, M> {
     pub fn is_crossfading(&self) -> bool {
         match self.core.crossfader.fading_state {
             FadingState::Approaching(_) => true,
             FadingState::Reached(_) => false,
         }
     }
+
+    // `input` and `output` have the same length, which is at most the maximum buffer size
+    fn process_chunk(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        let len = output.len();
+        if !self.is_crossfading() {
+            if let Some(update) = self.pending.pop_front() {
+                // the stored response has been validated by `try_queue_update` already
+                let start = update.slot * self.max_response_length;
+                match swap(
+                    &mut self.core,
+                    &self.stored_responses[start..start + update.len],
+                ) {
+                    Ok(()) => self.started(update.id),
+                    Err(_) => self.push_event(UpdateEvent::Dropped(update.id)),
+                }
+            }
+        }
+
+        // Outside of a crossfade only the target convolver is processed, the idle one is fed
+        // so that its history is complete once the next update fades it in
+        if !self.is_crossfading() {
+            let (target, idle) = match self.core.crossfader.fading_state.target() {
+                Target::A => (&mut self.core.convolver_a, &mut self.core.convolver_b),
+                Target::B => (&mut self.core.convolver_b, &mut self.core.convolver_a),
+            };
+            let result = target.try_process(input, output);
+            return result.and(idle.try_feed(input));
+        }
+
+        // While crossfading both convolvers are processed, a failing convolver recovers by
+        // itself and contributes silence to this block
+        let result_a = self
+            .core
+            .convolver_a
+            .try_process(input, &mut self.buffer_a[..len]);
+        let result_b = self
+            .core
+            .convolver_b
+            .try_process(input, &mut self.buffer_b[..len]);
+
+        self.core
+            .crossfader
+            .mix_block(&self.buffer_a[..len], &self.buffer_b[..len], output);
+        if !self.is_crossfading() {
+            self.finished();
+        }
+
+        result_a.and(result_b)
+    }
+
+    // Called when a crossfade is complete
+    fn finished(&mut self) {
+        let id = self.update_ids[self.core.crossfader.target() as usize];
+        self.push_event(UpdateEvent::Finished(id));
+    }
+
+    fn target_convolver(&self) -> &Convolver {
+        match self.core.crossfader.fading_state.target() {
+            Target::A => &self.core.convolver_a,
+            Target::B => &self.core.convolver_b,
+        }
+    }
 }
 
-fn swap<T: Convolution>(core: &mut CrossfadeConvolverCore<T>, response: &[Sample]) {
+/// Crossfade length of `Convolution::try_init`, about 43 ms at 48 kHz.
+pub const DEFAULT_CROSSFADE_SAMPLES: usize = 2048;
+
+// A duration in samples or seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Duration {
+    Samples(usize),
+    Seconds { seconds: f64, sample_rate: f64 },
+}
+
+impl Duration {
+    fn samples(self) -> Result<usize, ConvolutionError> {
+        match self {
+            Self::Samples(samples) => Ok(samples),
+            Self::Seconds {
+                seconds,
+                sample_rate,
+            } => {
+                if !(sample_rate.is_finite() && sample_rate > 0.0) {
+                    return Err(ConvolutionError::InvalidParameter(
+                        "the sample rate must be positive",
+                    ));
+                }
+                if !(seconds.is_finite() && seconds >= 0.0) {
+                    return Err(ConvolutionError::InvalidParameter(
+                        "durations must not be negative",
+                    ));
+                }
+                Ok((seconds * sample_rate).round() as usize)
+            }
+        }
+    }
+}
+
+/// Settings of a `CrossfadeConvolver`, see `CrossfadeConvolver::with_mixer`.
+///
+/// The crossfade defaults to `DEFAULT_CROSSFADE_SAMPLES`, the hold period to the maximum block
+/// size (at most the maximum response length), the mixer to the `RaisedCosineMixer` and the
+/// pending updates to one with `PendingUpdatePolicy::LatestWins`.
+#[derive(Clone, Debug)]
+pub struct CrossfadeConvolverBuilder<M: Mixer = RaisedCosineMixer> {
+    max_block_size: usize,
+    max_response_length: usize,
+    crossfade: Duration,
+    hold: Option<Duration>,
+    mixer: M,
+    pending_updates: usize,
+    policy: PendingUpdatePolicy,
+}
+
+impl CrossfadeConvolverBuilder {
+    /// Responses of up to `max_response_length` samples can be faded to, `process` is called
+    /// with up to `max_block_size` samples at a time (larger buffers are processed in chunks).
+    pub fn new(max_block_size: usize, max_response_length: usize) -> Self {
+        Self {
+            max_block_size,
+            max_response_length,
+            crossfade: Duration::Samples(DEFAULT_CROSSFADE_SAMPLES),
+            hold: None,
+            mixer: RaisedCosineMixer,
+            pending_updates: 1,
+            policy: PendingUpdatePolicy::LatestWins,
+        }
+    }
+}
+
+impl<M: Mixer> CrossfadeConvolverBuilder<M> {
+    pub fn crossfade_samples(mut self, samples: usize) -> Self {
+        self.crossfade = Duration::Samples(samples);
+        self
+    }
+
+    pub fn crossfade_seconds(mut self, seconds: f64, sample_rate: f64) -> Self {
+        self.crossfade = Duration::Seconds {
+            seconds,
+            sample_rate,
+        };
+        self
+    }
+
+    /// Time the idle convolver runs after an update before the new response fades in.
+    pub fn hold_samples(mut self, samples: usize) -> Self {
+        self.hold = Some(Duration::Samples(samples));
+        self
+    }
+
+    pub fn hold_seconds(mut self, seconds: f64, sample_rate: f64) -> Self {
+        self.hold = Some(Duration::Seconds {
+            seconds,
+            sample_rate,
+        });
+        self
+    }
+
+    pub fn mixer<N: Mixer>(self, mixer: N) -> CrossfadeConvolverBuilder<N> {
+        CrossfadeConvolverBuilder {
+            max_block_size: self.max_block_size,
+            max_response_length: self.max_response_length,
+            crossfade: self.crossfade,
+            hold: self.hold,
+            mixer,
+            pending_updates: self.pending_updates,
+            policy: self.policy,
+        }
+    }
+
+    /// See `CrossfadeConvolver::set_pending_updates`.
+    pub fn pending_updates(mut self, capacity: usize, policy: PendingUpdatePolicy) -> Self {
+        self.pending_updates = capacity;
+        self.policy = policy;
+        self
+    }
+
+    /// Creates the convolver with `Convolution::try_init` and validates the settings.
+    pub fn try_build<C: Convolution<S>, S: Sample>(
+        self,
+        response: &[S],
+    ) -> Result<CrossfadeConvolver<C, S, M>, ConvolutionError> {
+        if self.max_block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        if response.len() > self.max_response_length {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.max_response_length,
+            });
+        }
+        let crossfade_samples = self.crossfade.samples()?;
+        if crossfade_samples == 0 {
+            return Err(ConvolutionError::InvalidParameter(
+                "the crossfade must be at least one sample long",
+            ));
+        }
+        let hold_samples = match self.hold {
+            Some(hold) => hold.samples()?,
+            None => self.max_block_size.min(self.max_response_length),
+        };
+        if self.pending_updates == 0 {
+            return Err(ConvolutionError::InvalidParameter(
+                "at least one update must be able to wait for a crossfade",
+            ));
+        }
+
+        let convolver = C::try_init(response, self.max_block_size, self.max_response_length)?;
+        let mut convolver = CrossfadeConvolver::from_parts(
+            convolver,
+            self.max_response_length,
+            self.max_block_size,
+            crossfade_samples,
+            hold_samples,
+            self.mixer,
+        );
+        if self.pending_updates != 1 || self.policy != PendingUpdatePolicy::LatestWins {
+            convolver.set_pending_updates(self.pending_updates, self.policy);
+        }
+        Ok(convolver)
+    }
+
+    /// Like `try_build`, but panics on invalid settings.
+    pub fn build<C: Convolution<S>, S: Sample>(
+        self,
+        response: &[S],
+    ) -> CrossfadeConvolver<C, S, M> {
+        self.try_build(response)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+}
+
+// The idle convolver was fed the input since the previous crossfade, so its history is
+// complete, the hold period covers the output it drops on the update
+fn swap<T: Convolution<S>, S: Sample, M: Mixer>(
+    core: &mut CrossfadeConvolverCore<T, S, M>,
+    response: &[S],
+) -> Result<(), ConvolutionError> {
     match core.crossfader.fading_state.target() {
         Target::A => {
-            core.convolver_b.update(response);
+            core.convolver_b.try_update(response)?;
             core.crossfader.fade_into(Target::B);
         }
         Target::B => {
-            core.convolver_a.update(response);
+            core.convolver_a.try_update(response)?;
             core.crossfader.fade_into(Target::A);
         }
     }
+    Ok(())
 }
 
 #[test]
 fn test_crossfade_convolver_passthrough() {
-    let mut response = [0.0; 1024];
+    let mut response = [0.0f32; 1024];
     response[0] = 1.0;
     let mut convolver = CrossfadeConvolver::new(
         crate::fft_convolver::FFTConvolver::init(&response, 1024, response.len()),
@@ -114,62 +630,82 @@ fn test_crossfade_convolver_passthrough() {
     let mut output = vec![0.0; 1024];
     convolver.process(&input, &mut output);
 
-    for i in 0..1024 {
-        assert!((output[i] - 1.0).abs() < 1e-6);
+    for sample in output {
+        assert!((sample - 1.0).abs() < 1e-6);
     }
 }
 
-pub trait Mixer {
-    fn mix(&self, a: Sample, b: Sample, value: Sample) -> Sample;
+/// Shape of a crossfade, mixes `a` and `b` according to `value`, which runs from 0 (only `a`)
+/// to 1 (only `b`).
+///
+/// `Convolution::try_init` creates the mixer of a `CrossfadeConvolver` with `Default`.
+pub trait Mixer: Clone + Default {
+    fn mix<S: Sample>(&self, a: S, b: S, value: S) -> S;
 }
 
-#[allow(dead_code)]
-struct LinearMixer;
+/// Gains that sum up to one, which keeps the level of correlated signals, e.g. responses that
+/// only differ slightly.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LinearMixer;
 impl Mixer for LinearMixer {
-    fn mix(&self, a: Sample, b: Sample, value: Sample) -> Sample {
-        a * (1.0 - value) + b * value
+    fn mix<S: Sample>(&self, a: S, b: S, value: S) -> S {
+        a * (S::one() - value) + b * value
     }
 }
 
-#[allow(dead_code)]
-struct SquareRootMixer;
+/// Squared gains that sum up to one (equal power), which keeps the level of decorrelated
+/// signals, e.g. reverb tails.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SquareRootMixer;
 impl Mixer for SquareRootMixer {
-    fn mix(&self, a: Sample, b: Sample, value: Sample) -> Sample {
-        let gain1 = (1.0 - value).sqrt();
+    fn mix<S: Sample>(&self, a: S, b: S, value: S) -> S {
+        let gain1 = (S::one() - value).sqrt();
         let gain2 = value.sqrt();
         a * gain1 + b * gain2
     }
 }
-const PI_HALF: Sample = std::f32::consts::PI * 0.5;
 
-#[allow(dead_code)]
-struct CosineMixer;
+/// Equal power like the `SquareRootMixer`, but with a smoother start and end.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CosineMixer;
 impl Mixer for CosineMixer {
-    fn mix(&self, a: Sample, b: Sample, value: Sample) -> Sample {
-        let rad = PI_HALF * value;
+    fn mix<S: Sample>(&self, a: S, b: S, value: S) -> S {
+        let rad = S::FRAC_PI_2() * value;
         let gain1 = rad.cos();
         let gain2 = rad.sin();
         a * gain1 + b * gain2
     }
 }
 
-#[derive(Clone)]
-struct RaisedCosineMixer;
+/// Gains that sum up to one like the `LinearMixer`, but with a smoother start and end. This is
+/// the default of the `CrossfadeConvolver`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RaisedCosineMixer;
 impl Mixer for RaisedCosineMixer {
-    fn mix(&self, a: Sample, b: Sample, value: Sample) -> Sample {
-        let rad = PI_HALF * value;
+    fn mix<S: Sample>(&self, a: S, b: S, value: S) -> S {
+        let rad = S::FRAC_PI_2() * value;
         let gain1 = rad.cos().powi(2);
-        let gain2 = 1.0 - gain1;
+        let gain2 = S::one() - gain1;
         a * gain1 + b * gain2
     }
 }
 
-#[derive(Clone, Copy, PartialEq)]
-enum Target {
+/// One of the two inputs of a `Crossfader`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
     A,
     B,
 }
 
+impl Target {
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum FadingState {
     Reached(Target),
@@ -185,31 +721,43 @@ impl FadingState {
     }
 }
 
+/// Switches between two signals `a` and `b`, e.g. the outputs of two convolvers or a dry and a
+/// wet signal.
+///
+/// A switch keeps the previous target for a hold period and then fades over to the new target,
+/// the shape of the fade is defined by the `Mixer`. The crossfader starts at `Target::A`.
 #[derive(Clone)]
-pub struct Crossfader<T: Mixer> {
+pub struct Crossfader<T: Mixer, S: Sample = f32> {
     mixer: T,
     fading_samples: i64,
     hold_samples: i64,
     counter: i64,
-    mix_value_step: Sample,
-    mix_value: Sample,
+    mix_value_step: S,
+    mix_value: S,
     fading_state: FadingState,
 }
 
-impl<T: Mixer> Crossfader<T> {
-    fn new(mixer: T, fading_samples: usize, hold_samples: usize) -> Self {
+impl<T: Mixer, S: Sample> Crossfader<T, S> {
+    /// Creates a crossfader that holds the previous target for `hold_samples` samples after a
+    /// switch and then fades over `fading_samples` samples (at least one).
+    pub fn new(mixer: T, fading_samples: usize, hold_samples: usize) -> Self {
+        let fading_samples = fading_samples.max(1);
         Self {
             mixer,
             fading_samples: fading_samples as i64,
             hold_samples: hold_samples as i64,
             counter: 0,
-            mix_value_step: 1.0 / fading_samples as Sample,
-            mix_value: 0.0,
+            mix_value_step: S::one() / S::from_usize(fading_samples).unwrap(),
+            mix_value: S::zero(),
             fading_state: FadingState::Reached(Target::A),
         }
     }
 
-    fn fade_into(&mut self, target: Target) {
+    /// Starts switching to `target`, does nothing if it is the current target already.
+    ///
+    /// Switching back during a fade reverses the fade from its current position, switching
+    /// back during the hold period cancels the switch.
+    pub fn fade_into(&mut self, target: Target) {
         let current_target = self.fading_state.target();
         if current_target == target {
             return;
@@ -219,7 +767,11 @@ impl<T: Mixer> Crossfader<T> {
             FadingState::Reached(_) => {
                 self.counter = -self.hold_samples;
                 self.fading_state = FadingState::Approaching(target);
-                self.mix_value_step = -self.mix_value_step;
+                // The mix value rises towards B and falls towards A
+                self.mix_value_step = match target {
+                    Target::A => -self.mix_value_step.abs(),
+                    Target::B => self.mix_value_step.abs(),
+                };
             }
             FadingState::Approaching(_) => {
                 // note: should never be the case in the context of the crossfade convolver,
@@ -235,11 +787,67 @@ impl<T: Mixer> Crossfader<T> {
         }
     }
 
-    fn mix(&mut self, a: Sample, b: Sample) -> Sample {
+    /// The target that is faded to, or was faded to last.
+    pub fn target(&self) -> Target {
+        self.fading_state.target()
+    }
+
+    /// Whether a switch is in progress, including its hold period.
+    pub fn is_fading(&self) -> bool {
+        matches!(self.fading_state, FadingState::Approaching(_))
+    }
+
+    /// Progress of the fade towards the target from 0 to 1, 0 during the hold period and 1 once
+    /// the target is reached.
+    pub fn progress(&self) -> f64 {
+        match self.fading_state {
+            FadingState::Reached(_) => 1.0,
+            FadingState::Approaching(_) => self.counter.max(0) as f64 / self.fading_samples as f64,
+        }
+    }
+
+    /// Number of samples until the target is reached, including the rest of the hold period.
+    pub fn remaining(&self) -> usize {
+        match self.fading_state {
+            FadingState::Reached(_) => 0,
+            FadingState::Approaching(_) => (self.fading_samples - self.counter) as usize,
+        }
+    }
+
+    /// Jumps to the end of a running switch.
+    pub fn reset(&mut self) {
+        let target = self.fading_state.target();
+        self.fading_state = FadingState::Reached(target);
+        self.counter = 0;
+        self.mix_value = match target {
+            Target::A => S::zero(),
+            Target::B => S::one(),
+        };
+    }
+
+    /// Mixes `a` and `b` into `output` sample by sample, all of them need to have the same
+    /// length.
+    pub fn mix_block(&mut self, a: &[S], b: &[S], output: &mut [S]) {
+        assert_eq!(a.len(), output.len());
+        assert_eq!(b.len(), output.len());
+
+        match self.fading_state {
+            FadingState::Reached(Target::A) => output.copy_from_slice(a),
+            FadingState::Reached(Target::B) => output.copy_from_slice(b),
+            FadingState::Approaching(_) => {
+                for ((output, a), b) in output.iter_mut().zip(a).zip(b) {
+                    *output = self.mix(*a, *b);
+                }
+            }
+        }
+    }
+
+    /// Mixes a single pair of samples and advances the crossfader by one sample.
+    pub fn mix(&mut self, a: S, b: S) -> S {
         match self.fading_state {
             FadingState::Reached(target) => match target {
-                Target::A => return a,
-                Target::B => return b,
+                Target::A => a,
+                Target::B => b,
             },
             FadingState::Approaching(target) => {
                 self.counter += 1;
@@ -258,11 +866,11 @@ impl<T: Mixer> Crossfader<T> {
                     self.fading_state = FadingState::Reached(target);
                     match target {
                         Target::A => {
-                            self.mix_value = 0.0;
+                            self.mix_value = S::zero();
                             return a;
                         }
                         Target::B => {
-                            self.mix_value = 1.0;
+                            self.mix_value = S::one();
                             return b;
                         }
                     }
@@ -310,3 +918,40 @@ fn test_crossfader() {
         }
     }
 }
+
+#[test]
+fn test_crossfader_mix_block_and_progress() {
+    let mut crossfader = Crossfader::<LinearMixer>::new(LinearMixer, 4, 2);
+    let a = [1.0; 8];
+    let b = [3.0; 8];
+    let mut output = [0.0; 8];
+
+    crossfader.mix_block(&a, &b, &mut output);
+    assert_eq!(output, a);
+    assert_eq!(crossfader.target(), Target::A);
+    assert!(!crossfader.is_fading());
+    assert_eq!(crossfader.progress(), 1.0);
+
+    crossfader.fade_into(Target::B);
+    assert!(crossfader.is_fading());
+    assert_eq!(crossfader.target(), Target::B);
+    assert_eq!(crossfader.remaining(), 6);
+    assert_eq!(crossfader.progress(), 0.0);
+
+    crossfader.mix_block(&a[..4], &b[..4], &mut output[..4]);
+    assert_eq!(output[..4], [1.0, 1.0, 1.5, 2.0]);
+    assert_eq!(crossfader.remaining(), 2);
+    assert_eq!(crossfader.progress(), 0.5);
+
+    crossfader.mix_block(&a[4..], &b[4..], &mut output[4..]);
+    assert_eq!(output[4..], [2.5, 3.0, 3.0, 3.0]);
+    assert!(!crossfader.is_fading());
+    assert_eq!(crossfader.remaining(), 0);
+
+    // Fading back during the hold period cancels the switch
+    crossfader.fade_into(Target::A);
+    crossfader.fade_into(Target::B);
+    assert!(!crossfader.is_fading());
+    crossfader.mix_block(&a, &b, &mut output);
+    assert_eq!(output, b);
+}
@@ -1,18 +1,22 @@
 use realfft::{ComplexToReal, FftError, RealFftPlanner, RealToComplex};
 use rustfft::num_complex::Complex;
+use std::ops::Range;
 use std::sync::Arc;
 
-use crate::{Convolution, Sample};
+use crate::partitioned_response::{partition_count, PartitionedResponse};
+use crate::split_complex::{SplitSpectra, SplitSpectraMut, SplitSpectraRef};
+use crate::tail_worker::{TailWorker, UnderrunPolicy};
+use crate::{BlockSizePolicy, Convolution, ConvolutionError, Sample};
 
 #[derive(Clone)]
-pub struct Fft {
-    fft_forward: Arc<dyn RealToComplex<f32>>,
-    fft_inverse: Arc<dyn ComplexToReal<f32>>,
+pub struct Fft<S: Sample = f32> {
+    fft_forward: Arc<dyn RealToComplex<S>>,
+    fft_inverse: Arc<dyn ComplexToReal<S>>,
 }
 
-impl Default for Fft {
+impl<S: Sample> Default for Fft<S> {
     fn default() -> Self {
-        let mut planner = RealFftPlanner::<f32>::new();
+        let mut planner = RealFftPlanner::<S>::new();
         Self {
             fft_forward: planner.plan_fft_forward(0),
             fft_inverse: planner.plan_fft_inverse(0),
@@ -20,30 +24,30 @@ impl Default for Fft {
     }
 }
 
-impl std::fmt::Debug for Fft {
+impl<S: Sample> std::fmt::Debug for Fft<S> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "")
     }
 }
 
-impl Fft {
+impl<S: Sample> Fft<S> {
     pub fn init(&mut self, length: usize) {
-        let mut planner = RealFftPlanner::<f32>::new();
+        let mut planner = RealFftPlanner::<S>::new();
         self.fft_forward = planner.plan_fft_forward(length);
         self.fft_inverse = planner.plan_fft_inverse(length);
     }
 
-    pub fn forward(&self, input: &mut [f32], output: &mut [Complex<f32>]) -> Result<(), FftError> {
+    pub fn forward(&self, input: &mut [S], output: &mut [Complex<S>]) -> Result<(), FftError> {
         self.fft_forward.process(input, output)?;
         Ok(())
     }
 
-    pub fn inverse(&self, input: &mut [Complex<f32>], output: &mut [f32]) -> Result<(), FftError> {
+    pub fn inverse(&self, input: &mut [Complex<S>], output: &mut [S]) -> Result<(), FftError> {
         self.fft_inverse.process(input, output)?;
 
         // FFT Normalization
-        let len = output.len();
-        output.iter_mut().for_each(|bin| *bin /= len as f32);
+        let len = S::from_usize(output.len()).unwrap();
+        output.iter_mut().for_each(|bin| *bin /= len);
 
         Ok(())
     }
@@ -53,134 +57,191 @@ pub fn complex_size(size: usize) -> usize {
     (size / 2) + 1
 }
 
-pub fn copy_and_pad(dst: &mut [f32], src: &[f32], src_size: usize) {
+pub fn copy_and_pad<S: Sample>(dst: &mut [S], src: &[S], src_size: usize) {
     assert!(dst.len() >= src_size);
     dst[0..src_size].clone_from_slice(&src[0..src_size]);
-    dst[src_size..].iter_mut().for_each(|value| *value = 0.);
+    dst[src_size..]
+        .iter_mut()
+        .for_each(|value| *value = S::zero());
 }
 
-pub fn complex_multiply_accumulate(
-    result: &mut [Complex<f32>],
-    a: &[Complex<f32>],
-    b: &[Complex<f32>],
+/// Accumulates the element-wise products of all pairs of spectra in `a` and `b` into the single
+/// spectrum `result`, vectorized if the CPU supports it.
+pub fn complex_multiply_accumulate<S: Sample>(
+    result: SplitSpectraMut<S>,
+    a: SplitSpectraRef<S>,
+    b: SplitSpectraRef<S>,
 ) {
-    assert_eq!(result.len(), a.len());
-    assert_eq!(result.len(), b.len());
-    let len = result.len();
-    let end4 = 4 * (len / 4);
-    for i in (0..end4).step_by(4) {
-        result[i + 0].re += a[i + 0].re * b[i + 0].re - a[i + 0].im * b[i + 0].im;
-        result[i + 1].re += a[i + 1].re * b[i + 1].re - a[i + 1].im * b[i + 1].im;
-        result[i + 2].re += a[i + 2].re * b[i + 2].re - a[i + 2].im * b[i + 2].im;
-        result[i + 3].re += a[i + 3].re * b[i + 3].re - a[i + 3].im * b[i + 3].im;
-        result[i + 0].im += a[i + 0].re * b[i + 0].im + a[i + 0].im * b[i + 0].re;
-        result[i + 1].im += a[i + 1].re * b[i + 1].im + a[i + 1].im * b[i + 1].re;
-        result[i + 2].im += a[i + 2].re * b[i + 2].im + a[i + 2].im * b[i + 2].re;
-        result[i + 3].im += a[i + 3].re * b[i + 3].im + a[i + 3].im * b[i + 3].re;
-    }
-    for i in end4..len {
-        result[i].re += a[i].re * b[i].re - a[i].im * b[i].im;
-        result[i].im += a[i].re * b[i].im + a[i].im * b[i].re;
-    }
+    S::complex_multiply_accumulate(result, a, b);
 }
 
-pub fn sum(result: &mut [f32], a: &[f32], b: &[f32]) {
-    assert_eq!(result.len(), a.len());
-    assert_eq!(result.len(), b.len());
-    let len = result.len();
-    let end4 = 3 * (len / 4);
-    for i in (0..end4).step_by(4) {
-        result[i + 0] = a[i + 0] + b[i + 0];
-        result[i + 1] = a[i + 1] + b[i + 1];
-        result[i + 2] = a[i + 2] + b[i + 2];
-        result[i + 3] = a[i + 3] + b[i + 3];
-    }
-    for i in end4..len {
-        result[i] = a[i] + b[i];
+/// Stores the element-wise sum of `a` and `b` in `result`, vectorized if the CPU supports it.
+pub fn sum<S: Sample>(result: &mut [S], a: &[S], b: &[S]) {
+    S::sum(result, a, b);
+}
+
+// Accumulates the products of the response partitions within `partitions` and the input
+// segments into `result`, partition `i` is multiplied with the input segment
+// `(offset + i) % segments.count()`
+pub(crate) fn multiply_partitions<S: Sample>(
+    result: &mut SplitSpectra<S>,
+    response: &SplitSpectra<S>,
+    segments: &SplitSpectra<S>,
+    partitions: Range<usize>,
+    offset: usize,
+) {
+    let mut start = partitions.start;
+    while start < partitions.end {
+        let segment = (offset + start) % segments.count();
+        let end = partitions.end.min(start + segments.count() - segment);
+        complex_multiply_accumulate(
+            result.spectra_mut(0..1),
+            response.spectra(start..end),
+            segments.spectra(segment..segment + end - start),
+        );
+        start = end;
     }
 }
-#[derive(Default, Clone)]
-pub struct FFTConvolver {
+
+/// What happens to the signal history when the response of an `FFTConvolver` is replaced.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Keeps the input history, but drops the output of the previous response that is still
+    /// ringing into the current block (the overlap). From the next block on, the output
+    /// continues as if the new response had been in place all along.
+    #[default]
+    DropOverlap,
+    /// Keeps the signal history and applies the new response to it as well, so that the output
+    /// continues as if the new response had been in place all along (time-varying filtering).
+    /// Costs an additional inverse FFT and a multiplication with all partitions per update.
+    PreserveHistory,
+}
+
+/// Uniformly partitioned convolution.
+///
+/// The transformed response is held in a `PartitionedResponse`, which can be shared with other
+/// convolvers of the same block size, see `try_with_response`.
+#[derive(Default)]
+pub struct FFTConvolver<S: Sample = f32> {
     ir_len: usize,
     block_size: usize,
     _seg_size: usize,
     seg_count: usize,
     active_seg_count: usize,
     _fft_complex_size: usize,
-    segments: Vec<Vec<Complex<f32>>>,
-    segments_ir: Vec<Vec<Complex<f32>>>,
-    fft_buffer: Vec<f32>,
-    fft: Fft,
-    pre_multiplied: Vec<Complex<f32>>,
-    conv: Vec<Complex<f32>>,
-    overlap: Vec<f32>,
+    segments: SplitSpectra<S>,
+    response: Arc<PartitionedResponse<S>>,
+    fft_buffer: Vec<S>,
+    spectrum: Vec<Complex<S>>,
+    fft: Fft<S>,
+    pre_multiplied: SplitSpectra<S>,
+    conv: SplitSpectra<S>,
+    overlap: Vec<S>,
     current: usize,
-    input_buffer: Vec<f32>,
+    input_buffer: Vec<S>,
     input_buffer_fill: usize,
+    update_mode: UpdateMode,
+    // Amortised update, see `begin_update`
+    update_budget: usize,
+    update_response: Vec<S>,
+    update_len: usize,
+    update_partition: Option<usize>,
+    // Never shared, its partitions are swapped into the response once an update is complete
+    shadow: PartitionedResponse<S>,
 }
 
-impl Convolution for FFTConvolver {
-    fn init(impulse_response: &[Sample], block_size: usize, max_response_length: usize) -> Self {
-        if max_response_length < impulse_response.len() {
-            panic!(
-                "max_response_length must be at least the length of the initial impulse response"
-            );
+impl<S: Sample> FFTConvolver<S> {
+    /// Creates a convolver that derives its partition size from `block_size` according to
+    /// `policy`, `try_init` uses `BlockSizePolicy::RoundUp`.
+    pub fn try_with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Result<Self, ConvolutionError> {
+        if block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        let response = PartitionedResponse::try_with_capacity(
+            impulse_response,
+            policy.partition_size(block_size),
+            max_response_length,
+        )?;
+        Self::try_with_response(Arc::new(response), max_response_length)
+    }
+
+    /// Like `try_with_block_size_policy`, but panics on invalid arguments.
+    pub fn with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Self {
+        Self::try_with_block_size_policy(impulse_response, block_size, max_response_length, policy)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Creates a convolver for an already transformed response, the block size is the one the
+    /// response was partitioned with.
+    ///
+    /// The response is shared, not copied. Calling `update` on a convolver whose response is
+    /// shared allocates a copy of it, `set_response` never allocates. The response needs space
+    /// for `max_response_length` samples, see `PartitionedResponse::try_with_capacity`.
+    pub fn try_with_response(
+        response: Arc<PartitionedResponse<S>>,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        if max_response_length < response.len() {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: max_response_length,
+            });
+        }
+        if response.capacity() < max_response_length {
+            return Err(ConvolutionError::InsufficientCapacity {
+                capacity: response.capacity(),
+                max_length: max_response_length,
+            });
         }
-        let mut padded_ir = impulse_response.to_vec();
-        padded_ir.resize(max_response_length, 0.);
-        let ir_len = padded_ir.len();
 
-        let block_size = block_size.next_power_of_two();
+        let block_size = response.block_size();
         let seg_size = 2 * block_size;
-        let seg_count = (ir_len as f64 / block_size as f64).ceil() as usize;
-        let active_seg_count = seg_count;
+        let seg_count = (max_response_length + block_size - 1) / block_size;
+        let active_seg_count = response.partition_count();
         let fft_complex_size = complex_size(seg_size);
 
         // FFT
         let mut fft = Fft::default();
         fft.init(seg_size);
-        let mut fft_buffer = vec![0.; seg_size];
+        let fft_buffer = vec![S::zero(); seg_size];
+        let spectrum = vec![Complex::new(S::zero(), S::zero()); fft_complex_size];
 
         // prepare segments
-        let segments = vec![vec![Complex::new(0., 0.); fft_complex_size]; seg_count];
-        let mut segments_ir = Vec::new();
-
-        // prepare ir
-        for i in 0..seg_count {
-            let mut segment = vec![Complex::new(0., 0.); fft_complex_size];
-            let remaining = ir_len - (i * block_size);
-            let size_copy = if remaining >= block_size {
-                block_size
-            } else {
-                remaining
-            };
-            copy_and_pad(&mut fft_buffer, &padded_ir[i * block_size..], size_copy);
-            fft.forward(&mut fft_buffer, &mut segment).unwrap();
-            segments_ir.push(segment);
-        }
+        let segments = SplitSpectra::new(seg_count, fft_complex_size);
 
         // prepare convolution buffers
-        let pre_multiplied = vec![Complex::new(0., 0.); fft_complex_size];
-        let conv = vec![Complex::new(0., 0.); fft_complex_size];
-        let overlap = vec![0.; block_size];
+        let pre_multiplied = SplitSpectra::new(1, fft_complex_size);
+        let conv = SplitSpectra::new(1, fft_complex_size);
+        let overlap = vec![S::zero(); block_size];
 
         // prepare input buffer
-        let input_buffer = vec![0.; block_size];
+        let input_buffer = vec![S::zero(); block_size];
         let input_buffer_fill = 0;
 
         // reset current position
         let current = 0;
 
-        Self {
-            ir_len,
+        Ok(Self {
+            ir_len: max_response_length,
             block_size,
             _seg_size: seg_size,
             seg_count,
             active_seg_count,
             _fft_complex_size: fft_complex_size,
             segments,
-            segments_ir,
+            response,
             fft_buffer,
+            spectrum,
             fft,
             pre_multiplied,
             conv,
@@ -188,54 +249,334 @@ impl Convolution for FFTConvolver {
             current,
             input_buffer,
             input_buffer_fill,
+            update_mode: UpdateMode::DropOverlap,
+            update_budget: 0,
+            update_response: Vec::new(),
+            update_len: 0,
+            update_partition: None,
+            shadow: PartitionedResponse::default(),
+        })
+    }
+
+    /// Like `try_with_response`, but panics on invalid arguments.
+    pub fn with_response(
+        response: Arc<PartitionedResponse<S>>,
+        max_response_length: usize,
+    ) -> Self {
+        Self::try_with_response(response, max_response_length)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// The transformed response, e.g. to share it with further convolvers.
+    pub fn response(&self) -> &Arc<PartitionedResponse<S>> {
+        &self.response
+    }
+
+    /// Swaps in an already transformed response, the signal history is handled like on `update`.
+    ///
+    /// This neither allocates nor transforms anything and is real-time safe. `response` is
+    /// exchanged for the previous response, unless it is rejected. Either way, `response` holds
+    /// a response the convolver does not use afterwards, release it off the audio thread.
+    ///
+    /// The response has to be partitioned with the block size of the convolver and needs space
+    /// for its maximum response length (see `PartitionedResponse::try_with_capacity`), so that
+    /// later updates never allocate. If the FFT fails while the update mode is applied, the
+    /// response is in place nevertheless and only the signal history is lost.
+    pub fn set_response(
+        &mut self,
+        response: &mut Arc<PartitionedResponse<S>>,
+    ) -> Result<(), ConvolutionError> {
+        if response.block_size() != self.block_size {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "the response is partitioned with a different block size",
+            ));
         }
+        if response.len() > self.ir_len {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.ir_len,
+            });
+        }
+        if response.capacity() < self.ir_len {
+            return Err(ConvolutionError::InsufficientCapacity {
+                capacity: response.capacity(),
+                max_length: self.ir_len,
+            });
+        }
+
+        self.update_partition = None;
+        std::mem::swap(&mut self.response, response);
+        self.active_seg_count = self.response.partition_count();
+        if let Err(error) = self.apply_update_mode() {
+            self.reset();
+            return Err(error.into());
+        }
+        Ok(())
     }
 
-    fn update(&mut self, response: &[Sample]) {
-        let new_ir_len = response.len();
+    /// Selects what happens to the signal history on `update`, `set_response` and when an
+    /// update started with `begin_update` completes.
+    pub fn set_update_mode(&mut self, mode: UpdateMode) {
+        self.update_mode = mode;
+    }
 
-        if new_ir_len > self.ir_len {
-            panic!("New impulse response is longer than initialized length");
+    pub fn update_mode(&self) -> UpdateMode {
+        self.update_mode
+    }
+
+    // Called after the response was replaced
+    fn apply_update_mode(&mut self) -> Result<(), FftError> {
+        match self.update_mode {
+            UpdateMode::DropOverlap => {
+                self.overlap.fill(S::zero());
+                self.multiply_preceding_segments();
+                Ok(())
+            }
+            UpdateMode::PreserveHistory => self.reapply_history(),
         }
+    }
 
-        if self.ir_len == 0 {
-            return;
+    // Within a block, the products of the preceding segments are only calculated once at its
+    // start
+    fn multiply_preceding_segments(&mut self) {
+        if self.input_buffer_fill > 0 {
+            self.pre_multiplied.clear();
+            multiply_partitions(
+                &mut self.pre_multiplied,
+                self.response.partitions(),
+                &self.segments,
+                1..self.active_seg_count,
+                self.current,
+            );
         }
+    }
 
-        self.fft_buffer.fill(0.);
-        self.conv.fill(Complex::new(0., 0.));
-        self.pre_multiplied.fill(Complex::new(0., 0.));
-        self.overlap.fill(0.);
+    // Recalculates everything derived from the previous response: the overlap of the previous
+    // block and, within a block, the products of the preceding segments
+    fn reapply_history(&mut self) -> Result<(), FftError> {
+        let partitions = self.response.partitions();
+
+        self.conv.clear();
+        multiply_partitions(
+            &mut self.conv,
+            partitions,
+            &self.segments,
+            0..self.active_seg_count,
+            self.current + 1,
+        );
+        self.conv.load(0, &mut self.spectrum);
+        self.fft.inverse(&mut self.spectrum, &mut self.fft_buffer)?;
+        self.overlap
+            .copy_from_slice(&self.fft_buffer[self.block_size..2 * self.block_size]);
+
+        self.multiply_preceding_segments();
+        Ok(())
+    }
 
-        self.active_seg_count = ((new_ir_len as f64 / self.block_size as f64).ceil()) as usize;
+    /// Limits the number of partitions `begin_update` transforms per processed block, so that
+    /// the cost of an update is spread over several blocks. A budget of zero makes
+    /// `begin_update` behave like `update`.
+    ///
+    /// The first call with a non-zero budget allocates a second response of the maximum
+    /// response length, which is transformed while the current one is still in use. This is
+    /// not real-time safe.
+    pub fn try_set_update_budget(
+        &mut self,
+        partitions_per_block: usize,
+    ) -> Result<(), ConvolutionError> {
+        if partitions_per_block > 0 && self.update_response.len() != self.ir_len {
+            self.shadow =
+                PartitionedResponse::try_with_capacity(&[], self.block_size, self.ir_len)?;
+            self.update_response = vec![S::zero(); self.ir_len];
+        }
+        self.update_budget = partitions_per_block;
+        Ok(())
+    }
+
+    /// Like `try_set_update_budget`, but panics if the second response cannot be set up.
+    pub fn set_update_budget(&mut self, partitions_per_block: usize) {
+        self.try_set_update_budget(partitions_per_block)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    /// Starts an update that is completed over the following calls to `process`.
+    ///
+    /// Each call to `process` transforms up to the budget set with `set_update_budget` of the
+    /// partitions of `response` and keeps convolving with the previous response in the
+    /// meantime. Once all partitions are transformed, the new response is swapped in and the
+    /// signal history is handled like on `update`, before the block is processed. Starting an
+    /// update while another one is pending replaces the pending one.
+    ///
+    /// This is real-time safe, unless the budget is zero, in which case the response is
+    /// transformed right away like with `update`. The transformed partitions are swapped into
+    /// the current response, which must not be shared with other convolvers (see
+    /// `try_with_response`). Sharing it while the update is pending, e.g. through a clone of
+    /// `response`, makes the completion allocate a copy.
+    pub fn try_begin_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        if self.update_budget == 0 {
+            return self.try_update(response);
+        }
+        if response.len() > self.ir_len {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.ir_len,
+            });
+        }
+        if Arc::strong_count(&self.response) > 1 {
+            return Err(ConvolutionError::SharedResponse);
+        }
+
+        self.update_response[..response.len()].copy_from_slice(response);
+        self.update_len = response.len();
+        self.update_partition = Some(0);
+        self.shadow.clear(response.len());
+        Ok(())
+    }
+
+    /// Like `try_begin_update`, but panics on invalid arguments.
+    pub fn begin_update(&mut self, response: &[S]) {
+        self.try_begin_update(response)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
 
-        // Prepare IR
-        for i in 0..self.active_seg_count {
-            let segment = &mut self.segments_ir[i];
-            let remaining = new_ir_len - (i * self.block_size);
-            let size_copy = if remaining >= self.block_size {
-                self.block_size
-            } else {
-                remaining
-            };
-            copy_and_pad(
+    /// Whether an update started with `begin_update` has not been completed yet.
+    pub fn update_pending(&self) -> bool {
+        self.update_partition.is_some()
+    }
+
+    // Transforms the next partitions of a pending update and swaps in the response once it is
+    // complete
+    fn continue_update(&mut self) -> Result<(), FftError> {
+        let Some(next) = self.update_partition else {
+            return Ok(());
+        };
+
+        let response = &self.update_response[..self.update_len];
+        let count = partition_count(response.len(), self.block_size);
+        let end = match self.update_budget {
+            0 => count,
+            budget => count.min(next + budget),
+        };
+
+        for index in next..end {
+            if let Err(error) = self.shadow.transform_partition(
+                index,
+                response,
+                &self.fft,
                 &mut self.fft_buffer,
-                &response[i * self.block_size..],
-                size_copy,
-            );
-            self.fft.forward(&mut self.fft_buffer, segment).unwrap();
+                &mut self.spectrum,
+            ) {
+                self.update_partition = None;
+                return Err(error);
+            }
         }
 
-        // Clear remaining segments
-        for i in self.active_seg_count..self.seg_count {
-            self.segments_ir[i].fill(Complex::new(0., 0.));
+        if end < count {
+            self.update_partition = Some(end);
+            return Ok(());
         }
+
+        self.shadow.finish(response.len());
+        std::mem::swap(Arc::make_mut(&mut self.response), &mut self.shadow);
+        self.active_seg_count = self.response.partition_count();
+        self.update_partition = None;
+        self.apply_update_mode()
+    }
+}
+
+/// Cloning copies the response instead of sharing it, so that `update` never has to allocate
+/// in either of the convolvers. Use `try_with_response` to share a response.
+impl<S: Sample> Clone for FFTConvolver<S> {
+    fn clone(&self) -> Self {
+        Self {
+            ir_len: self.ir_len,
+            block_size: self.block_size,
+            _seg_size: self._seg_size,
+            seg_count: self.seg_count,
+            active_seg_count: self.active_seg_count,
+            _fft_complex_size: self._fft_complex_size,
+            segments: self.segments.clone(),
+            response: Arc::new(PartitionedResponse::clone(&self.response)),
+            fft_buffer: self.fft_buffer.clone(),
+            spectrum: self.spectrum.clone(),
+            fft: self.fft.clone(),
+            pre_multiplied: self.pre_multiplied.clone(),
+            conv: self.conv.clone(),
+            overlap: self.overlap.clone(),
+            current: self.current,
+            input_buffer: self.input_buffer.clone(),
+            input_buffer_fill: self.input_buffer_fill,
+            update_mode: self.update_mode,
+            update_budget: self.update_budget,
+            update_response: self.update_response.clone(),
+            update_len: self.update_len,
+            update_partition: self.update_partition,
+            shadow: self.shadow.clone(),
+        }
+    }
+}
+
+impl<S: Sample> Convolution<S> for FFTConvolver<S> {
+    fn try_init(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Self::try_with_block_size_policy(
+            impulse_response,
+            block_size,
+            max_response_length,
+            BlockSizePolicy::RoundUp,
+        )
     }
 
-    fn process(&mut self, input: &[Sample], output: &mut [Sample]) {
-        if self.active_seg_count == 0 {
-            output.fill(0.);
-            return;
+    /// Transforms the response in place, if the response is shared with other convolvers it is
+    /// copied first (see `try_with_response`). The signal history is handled according to the
+    /// update mode, see `set_update_mode`.
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        let new_ir_len = response.len();
+
+        if new_ir_len > self.ir_len {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: new_ir_len,
+                max_length: self.ir_len,
+            });
+        }
+
+        self.update_partition = None;
+
+        let result = Arc::make_mut(&mut self.response).transform(
+            response,
+            &self.fft,
+            &mut self.fft_buffer,
+            &mut self.spectrum,
+        );
+        self.active_seg_count = self.response.partition_count();
+        if let Err(error) = result.and_then(|_| self.apply_update_mode()) {
+            self.reset();
+            return Err(error.into());
+        }
+
+        Ok(())
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if input.len() != output.len() {
+            return Err(ConvolutionError::BufferSizeMismatch {
+                expected: output.len(),
+                actual: input.len(),
+            });
+        }
+
+        if let Err(error) = self.continue_update() {
+            self.recover(output);
+            return Err(error.into());
+        }
+
+        // Without space for a response there is no signal history to keep either
+        if self.seg_count == 0 {
+            output.fill(S::zero());
+            return Ok(());
         }
 
         let mut processed = 0;
@@ -252,38 +593,41 @@ impl Convolution for FFTConvolver {
 
             // Forward FFT
             copy_and_pad(&mut self.fft_buffer, &self.input_buffer, self.block_size);
-            if let Err(_err) = self
-                .fft
-                .forward(&mut self.fft_buffer, &mut self.segments[self.current])
-            {
-                output.fill(0.);
-                return; // error!
+            if let Err(error) = self.fft.forward(&mut self.fft_buffer, &mut self.spectrum) {
+                self.recover(output);
+                return Err(error.into());
             }
+            self.segments.store(self.current, &self.spectrum);
 
             // complex multiplication
             if input_buffer_was_empty {
-                self.pre_multiplied.fill(Complex { re: 0., im: 0. });
-                for i in 1..self.active_seg_count {
-                    let index_ir = i;
-                    let index_audio = (self.current + i) % self.active_seg_count;
-                    complex_multiply_accumulate(
-                        &mut self.pre_multiplied,
-                        &self.segments_ir[index_ir],
-                        &self.segments[index_audio],
-                    );
-                }
+                // Segment `i` of the response is multiplied with the input segment
+                // `(current + i) % seg_count`
+                self.pre_multiplied.clear();
+                multiply_partitions(
+                    &mut self.pre_multiplied,
+                    self.response.partitions(),
+                    &self.segments,
+                    1..self.active_seg_count,
+                    self.current,
+                );
+            }
+            // The input is stored even while the response is empty, so that the history is
+            // complete once a longer response is in place again
+            self.conv.copy_from(&self.pre_multiplied);
+            if self.active_seg_count > 0 {
+                complex_multiply_accumulate(
+                    self.conv.spectra_mut(0..1),
+                    self.segments.spectra(self.current..self.current + 1),
+                    self.response.partitions().spectra(0..1),
+                );
             }
-            self.conv.clone_from_slice(&self.pre_multiplied);
-            complex_multiply_accumulate(
-                &mut self.conv,
-                &self.segments[self.current],
-                &self.segments_ir[0],
-            );
 
             // Backward FFT
-            if let Err(_err) = self.fft.inverse(&mut self.conv, &mut self.fft_buffer) {
-                output.fill(0.);
-                return; // error!
+            self.conv.load(0, &mut self.spectrum);
+            if let Err(error) = self.fft.inverse(&mut self.spectrum, &mut self.fft_buffer) {
+                self.recover(output);
+                return Err(error.into());
             }
 
             // Add overlap
@@ -297,7 +641,7 @@ impl Convolution for FFTConvolver {
             self.input_buffer_fill += processing;
             if self.input_buffer_fill == self.block_size {
                 // Input buffer is empty again now
-                self.input_buffer.fill(0.);
+                self.input_buffer.fill(S::zero());
                 self.input_buffer_fill = 0;
                 // Save the overlap
                 self.overlap
@@ -307,122 +651,494 @@ impl Convolution for FFTConvolver {
                 self.current = if self.current > 0 {
                     self.current - 1
                 } else {
-                    self.active_seg_count - 1
+                    self.seg_count - 1
                 };
             }
             processed += processing;
         }
+
+        Ok(())
+    }
+
+    /// Only transforms the completed input blocks, the products with the response are
+    /// calculated once processing continues.
+    fn try_feed(&mut self, input: &[S]) -> Result<(), ConvolutionError> {
+        if let Err(error) = self.continue_update() {
+            self.reset();
+            return Err(error.into());
+        }
+
+        if self.seg_count == 0 {
+            return Ok(());
+        }
+
+        let mut fed = 0;
+        let mut blocks = 0;
+        while fed < input.len() {
+            let processing =
+                std::cmp::min(input.len() - fed, self.block_size - self.input_buffer_fill);
+            let input_buffer_pos = self.input_buffer_fill;
+            self.input_buffer[input_buffer_pos..input_buffer_pos + processing]
+                .clone_from_slice(&input[fed..fed + processing]);
+            self.input_buffer_fill += processing;
+            fed += processing;
+
+            if self.input_buffer_fill == self.block_size {
+                copy_and_pad(&mut self.fft_buffer, &self.input_buffer, self.block_size);
+                if let Err(error) = self.fft.forward(&mut self.fft_buffer, &mut self.spectrum) {
+                    self.reset();
+                    return Err(error.into());
+                }
+                self.segments.store(self.current, &self.spectrum);
+
+                self.input_buffer.fill(S::zero());
+                self.input_buffer_fill = 0;
+                self.current = if self.current > 0 {
+                    self.current - 1
+                } else {
+                    self.seg_count - 1
+                };
+                blocks += 1;
+            }
+        }
+
+        // The overlap of the fed blocks is dropped, and the products calculated at the start of
+        // the current block belong to the segments before the feed
+        if blocks > 0 {
+            self.overlap.fill(S::zero());
+            self.multiply_preceding_segments();
+        }
+
+        Ok(())
+    }
+
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.ir_len
+    }
+
+    fn response_length(&self) -> usize {
+        self.response.len()
+    }
+
+    fn reset(&mut self) {
+        self.fft_buffer.fill(S::zero());
+        self.spectrum.fill(Complex::new(S::zero(), S::zero()));
+        self.conv.clear();
+        self.pre_multiplied.clear();
+        self.overlap.fill(S::zero());
+        self.segments.clear();
+        self.input_buffer.fill(S::zero());
+        self.input_buffer_fill = 0;
+        self.current = 0;
+    }
+}
+
+impl<S: Sample> FFTConvolver<S> {
+    // A failed FFT leaves the current segment and the convolution buffers undefined, the signal
+    // history is dropped so that processing continues from a consistent (silent) state.
+    fn recover(&mut self, output: &mut [S]) {
+        self.reset();
+        output.fill(S::zero());
     }
 }
 
 #[test]
 fn test_fft_convolver_passthrough() {
-    let mut response = [0.0; 1024];
+    let mut response = [0.0f32; 1024];
     response[0] = 1.0;
     let mut convolver = FFTConvolver::init(&response, 1024, response.len());
     let input = vec![1.0; 1024];
     let mut output = vec![0.0; 1024];
     convolver.process(&input, &mut output);
 
-    for i in 0..1024 {
-        assert!((output[i] - 1.0).abs() < 1e-6);
+    for sample in output {
+        assert!((sample - 1.0).abs() < 1e-6);
+    }
+}
+
+#[test]
+fn test_fft_convolver_recovers_from_fft_failure() {
+    let block_size = 64;
+    let response: Vec<f32> = (0..300).map(|i| 1.0 / (1 + i) as f32).collect();
+    let input: Vec<f32> = (0..4 * block_size)
+        .map(|i| (i as f32 * 0.1).sin())
+        .collect();
+    let mut convolver = FFTConvolver::init(&response, block_size, response.len());
+    let mut convolver_fresh = convolver.clone();
+    let mut output = vec![0.0; block_size];
+    let mut output_fresh = vec![0.0; block_size];
+
+    convolver.process(&input[..block_size], &mut output);
+
+    // a plan of the wrong size makes the forward FFT fail
+    convolver.fft.init(block_size);
+    let result = convolver.try_process(&input[block_size..2 * block_size], &mut output);
+    assert!(matches!(result, Err(ConvolutionError::Fft(_))));
+    assert!(output.iter().all(|sample| *sample == 0.0));
+
+    // once the FFT works again, the convolver continues like a freshly initialized one
+    convolver.fft.init(2 * block_size);
+    for input_block in input[2 * block_size..].chunks_exact(block_size) {
+        convolver.try_process(input_block, &mut output).unwrap();
+        convolver_fresh.process(input_block, &mut output_fresh);
+        for (sample, sample_fresh) in output.iter().zip(&output_fresh) {
+            assert!((sample - sample_fresh).abs() < 1e-6);
+        }
     }
 }
 
 #[derive(Clone)]
-pub struct TwoStageFFTConvolver {
-    head_convolver: FFTConvolver,
-    tail_convolver0: FFTConvolver,
-    tail_output0: Vec<Sample>,
-    tail_precalculated0: Vec<Sample>,
-    tail_convolver: FFTConvolver,
-    tail_output: Vec<Sample>,
-    tail_precalculated: Vec<Sample>,
-    tail_input: Vec<Sample>,
+pub struct TwoStageFFTConvolver<S: Sample = f32> {
+    ir_len: usize,
+    response_len: usize,
+    head_block_size: usize,
+    tail_block_size: usize,
+    head_convolver: FFTConvolver<S>,
+    tail_convolver0: FFTConvolver<S>,
+    tail_output0: Vec<S>,
+    tail_precalculated0: Vec<S>,
+    tail_convolver: FFTConvolver<S>,
+    tail_worker: Option<TailWorker<S>>,
+    tail_output: Vec<S>,
+    tail_precalculated: Vec<S>,
+    tail_input: Vec<S>,
     tail_input_fill: usize,
     precalculated_pos: usize,
 }
 
-const HEAD_BLOCK_SIZE: usize = 128;
-const TAIL_BLOCK_SIZE: usize = 1024;
-
-impl Convolution for TwoStageFFTConvolver {
-    fn init(impulse_response: &[Sample], _block_size: usize, max_response_length: usize) -> Self {
-        let head_block_size = HEAD_BLOCK_SIZE;
-        let tail_block_size = TAIL_BLOCK_SIZE;
-
+const MIN_TAIL_BLOCK_SIZE: usize = 1024;
+const TAIL_TO_HEAD_RATIO: usize = 8;
+
+impl<S: Sample> TwoStageFFTConvolver<S> {
+    /// Creates a convolver with explicit partition sizes for the head and the tail.
+    ///
+    /// `tail_block_size` must be a multiple of `head_block_size`, both sizes are used as they
+    /// are. The head runs at `head_block_size` and determines the cost per
+    /// processed block, the tail partitions the remainder of the response in blocks of
+    /// `tail_block_size`.
+    pub fn try_with_block_sizes(
+        impulse_response: &[S],
+        head_block_size: usize,
+        tail_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        if head_block_size == 0 || tail_block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        if tail_block_size < head_block_size || tail_block_size % head_block_size != 0 {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "tail_block_size must be a multiple of head_block_size",
+            ));
+        }
         if max_response_length < impulse_response.len() {
-            panic!(
-                "max_response_length must be at least the length of the initial impulse response"
-            );
+            return Err(ConvolutionError::ResponseTooLong {
+                length: impulse_response.len(),
+                max_length: max_response_length,
+            });
         }
         let mut padded_ir = impulse_response.to_vec();
-        padded_ir.resize(max_response_length, 0.);
+        padded_ir.resize(max_response_length, S::zero());
 
         let head_ir_len = std::cmp::min(max_response_length, tail_block_size);
-        let head_convolver = FFTConvolver::init(
+        let head_convolver = FFTConvolver::try_with_block_size_policy(
             &padded_ir[0..head_ir_len],
             head_block_size,
-            max_response_length,
-        );
-
-        let tail_convolver0 = (max_response_length > tail_block_size)
-            .then(|| {
-                let tail_ir_len =
-                    std::cmp::min(max_response_length - tail_block_size, tail_block_size);
-                FFTConvolver::init(
-                    &padded_ir[tail_block_size..tail_block_size + tail_ir_len],
-                    head_block_size,
-                    max_response_length,
-                )
-            })
-            .unwrap_or_default();
-
-        let tail_output0 = vec![0.0; tail_block_size];
-        let tail_precalculated0 = vec![0.0; tail_block_size];
-
-        let tail_convolver = (max_response_length > 2 * tail_block_size)
-            .then(|| {
-                let tail_ir_len = max_response_length - 2 * tail_block_size;
-                FFTConvolver::init(
-                    &padded_ir[2 * tail_block_size..2 * tail_block_size + tail_ir_len],
-                    tail_block_size,
-                    max_response_length,
-                )
-            })
-            .unwrap_or_default();
-
-        let tail_output = vec![0.0; tail_block_size];
-        let tail_precalculated = vec![0.0; tail_block_size];
-        let tail_input = vec![0.0; tail_block_size];
+            head_ir_len,
+            BlockSizePolicy::Exact,
+        )?;
+
+        let tail_convolver0 = if max_response_length > tail_block_size {
+            let tail_ir_len = std::cmp::min(max_response_length - tail_block_size, tail_block_size);
+            FFTConvolver::try_with_block_size_policy(
+                &padded_ir[tail_block_size..tail_block_size + tail_ir_len],
+                head_block_size,
+                tail_ir_len,
+                BlockSizePolicy::Exact,
+            )?
+        } else {
+            FFTConvolver::default()
+        };
+
+        let tail_output0 = vec![S::zero(); tail_block_size];
+        let tail_precalculated0 = vec![S::zero(); tail_block_size];
+
+        let tail_convolver = if max_response_length > 2 * tail_block_size {
+            let tail_ir_len = max_response_length - 2 * tail_block_size;
+            FFTConvolver::try_with_block_size_policy(
+                &padded_ir[2 * tail_block_size..2 * tail_block_size + tail_ir_len],
+                tail_block_size,
+                tail_ir_len,
+                BlockSizePolicy::Exact,
+            )?
+        } else {
+            FFTConvolver::default()
+        };
+
+        let tail_output = vec![S::zero(); tail_block_size];
+        let tail_precalculated = vec![S::zero(); tail_block_size];
+        let tail_input = vec![S::zero(); tail_block_size];
         let tail_input_fill = 0;
         let precalculated_pos = 0;
 
-        TwoStageFFTConvolver {
+        Ok(TwoStageFFTConvolver {
+            ir_len: max_response_length,
+            response_len: impulse_response.len(),
+            head_block_size,
+            tail_block_size,
             head_convolver,
             tail_convolver0,
             tail_output0,
             tail_precalculated0,
             tail_convolver,
+            tail_worker: None,
             tail_output,
             tail_precalculated,
             tail_input,
             tail_input_fill,
             precalculated_pos,
+        })
+    }
+
+    /// Like `try_with_block_sizes`, but panics on invalid arguments.
+    pub fn with_block_sizes(
+        impulse_response: &[S],
+        head_block_size: usize,
+        tail_block_size: usize,
+        max_response_length: usize,
+    ) -> Self {
+        Self::try_with_block_sizes(
+            impulse_response,
+            head_block_size,
+            tail_block_size,
+            max_response_length,
+        )
+        .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Partition size of the head, i.e. the block size the response is processed with.
+    pub fn head_block_size(&self) -> usize {
+        self.head_block_size
+    }
+
+    /// Partition size of the tail.
+    pub fn tail_block_size(&self) -> usize {
+        self.tail_block_size
+    }
+
+    /// Moves the convolution of the 2nd-Nth tail block to a dedicated worker thread.
+    ///
+    /// The audio thread then only hands completed tail input blocks to the worker and sums the
+    /// precalculated results, which removes the CPU spike every `tail_block_size` samples.
+    /// `underrun_policy` defines what happens if the worker misses its deadline. Has no effect
+    /// if the response is too short to have a 2nd tail block.
+    ///
+    /// `try_update` fails with `ConvolutionError::QueueFull` while the worker has not picked up
+    /// the previous updates yet, in which case the convolver keeps the previous response.
+    pub fn try_with_background_tail(
+        mut self,
+        underrun_policy: UnderrunPolicy,
+    ) -> Result<Self, ConvolutionError> {
+        if self.tail_worker.is_none() && self.ir_len > 2 * self.tail_block_size {
+            self.tail_worker = Some(TailWorker::spawn(
+                std::mem::take(&mut self.tail_convolver),
+                self.tail_block_size,
+                self.ir_len - 2 * self.tail_block_size,
+                underrun_policy,
+            )?);
         }
+        Ok(self)
     }
 
-    fn update(&mut self, _response: &[Sample]) {
-        todo!()
+    /// Like `try_with_background_tail`, but panics if the worker thread cannot be spawned.
+    pub fn with_background_tail(self, underrun_policy: UnderrunPolicy) -> Self {
+        self.try_with_background_tail(underrun_policy)
+            .unwrap_or_else(|error| panic!("{error}"))
     }
 
-    fn process(&mut self, input: &[Sample], output: &mut [Sample]) {
+    /// Derives the partition sizes from the host block size: the head uses `block_size`
+    /// according to `policy`, the tail uses eight times that but at least 1024 samples.
+    pub fn try_with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Result<Self, ConvolutionError> {
+        if block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        let head_block_size = policy.partition_size(block_size);
+        let tail_to_head_ratio = std::cmp::max(
+            TAIL_TO_HEAD_RATIO,
+            (MIN_TAIL_BLOCK_SIZE + head_block_size - 1) / head_block_size,
+        );
+        Self::try_with_block_sizes(
+            impulse_response,
+            head_block_size,
+            tail_to_head_ratio * head_block_size,
+            max_response_length,
+        )
+    }
+
+    /// Like `try_with_block_size_policy`, but panics on invalid arguments.
+    pub fn with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Self {
+        Self::try_with_block_size_policy(impulse_response, block_size, max_response_length, policy)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Number of tail blocks the background worker did not deliver in time.
+    pub fn tail_underruns(&self) -> usize {
+        self.tail_worker.as_ref().map_or(0, TailWorker::underruns)
+    }
+}
+
+impl<S: Sample> Convolution<S> for TwoStageFFTConvolver<S> {
+    /// See `try_with_block_size_policy`, the block size is rounded up to a power of two.
+    fn try_init(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Self::try_with_block_size_policy(
+            impulse_response,
+            block_size,
+            max_response_length,
+            BlockSizePolicy::RoundUp,
+        )
+    }
+
+    /// If a stage fails to transform its part of the response, all stages fall back to the
+    /// empty response and drop their signal history, so that they never hold parts of
+    /// different responses.
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        let new_ir_len = response.len();
+
+        if new_ir_len > self.ir_len {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: new_ir_len,
+                max_length: self.ir_len,
+            });
+        }
+        // A background worker can reject the update, before any of the stages changed
+        if let Some(worker) = &self.tail_worker {
+            let tail_len = new_ir_len.saturating_sub(2 * self.tail_block_size);
+            if !worker.can_update(tail_len) {
+                return Err(ConvolutionError::QueueFull);
+            }
+        }
+
+        if let Err(error) = self.update_stages(response) {
+            // Updating to the empty response does not transform anything
+            let _ = self.update_stages(&[]);
+            self.reset();
+            self.response_len = 0;
+            return Err(error);
+        }
+
+        // The precalculated tail belongs to the previous response, it is dropped like the
+        // overlap of an `FFTConvolver`. The tail input is kept as part of the signal history.
+        self.clear_tail_output();
+        self.response_len = new_ir_len;
+
+        Ok(())
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if let Err(error) = self.process_stages(input, Some(&mut *output)) {
+            self.recover(output);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Feeds every stage, the output the tail stages precalculate for the fed input is dropped.
+    fn try_feed(&mut self, input: &[S]) -> Result<(), ConvolutionError> {
+        if let Err(error) = self.process_stages(input, None) {
+            self.reset();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // The tail is precalculated, so it does not add any latency on top of the head
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn block_size(&self) -> usize {
+        self.head_block_size
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.ir_len
+    }
+
+    fn response_length(&self) -> usize {
+        self.response_len
+    }
+
+    fn reset(&mut self) {
+        self.head_convolver.reset();
+        self.tail_convolver0.reset();
+        match &mut self.tail_worker {
+            Some(worker) => worker.reset(),
+            None => self.tail_convolver.reset(),
+        }
+        self.clear_tail();
+    }
+}
+
+impl<S: Sample> TwoStageFFTConvolver<S> {
+    // Distributes the response across the stages, each stage receives the part of the response
+    // that falls into its range (possibly empty), so no stage exceeds the length it was
+    // initialized with. The worker is updated last, its queue was checked before.
+    fn update_stages(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        let tail_block_size = self.tail_block_size;
+        let head_end = std::cmp::min(response.len(), tail_block_size);
+        let tail0_end = std::cmp::min(response.len(), 2 * tail_block_size);
+
+        self.head_convolver.try_update(&response[0..head_end])?;
+        if self.ir_len > tail_block_size {
+            self.tail_convolver0
+                .try_update(&response[head_end..tail0_end])?;
+        }
+        match &mut self.tail_worker {
+            Some(worker) => worker.update(&response[tail0_end..])?,
+            None if self.ir_len > 2 * tail_block_size => {
+                self.tail_convolver.try_update(&response[tail0_end..])?
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    // Without an output the stages are only fed
+    fn process_stages(
+        &mut self,
+        input: &[S],
+        mut output: Option<&mut [S]>,
+    ) -> Result<(), ConvolutionError> {
         // Head
-        self.head_convolver.process(input, output);
+        match output.as_deref_mut() {
+            Some(output) => self.head_convolver.try_process(input, output)?,
+            None => self.head_convolver.try_feed(input)?,
+        }
 
         // Tail
         if self.tail_input.is_empty() {
-            return;
+            return Ok(());
         }
 
         let len = input.len();
@@ -432,28 +1148,30 @@ impl Convolution for TwoStageFFTConvolver {
             let remaining = len - processed;
             let processing = std::cmp::min(
                 remaining,
-                HEAD_BLOCK_SIZE - (self.tail_input_fill % HEAD_BLOCK_SIZE),
+                self.head_block_size - (self.tail_input_fill % self.head_block_size),
             );
 
             // Sum head and tail
             let sum_begin = processed;
             let sum_end = processed + processing;
-
-            // Sum: 1st tail block
-            if self.tail_precalculated0.len() > 0 {
-                let mut precalculated_pos = self.precalculated_pos;
-                for i in sum_begin..sum_end {
-                    output[i] += self.tail_precalculated0[precalculated_pos];
-                    precalculated_pos += 1;
+            let precalculated_begin = self.precalculated_pos;
+            let precalculated_end = self.precalculated_pos + processing;
+
+            if let Some(output) = output.as_deref_mut() {
+                // Sum: 1st tail block
+                if !self.tail_precalculated0.is_empty() {
+                    output[sum_begin..sum_end]
+                        .iter_mut()
+                        .zip(&self.tail_precalculated0[precalculated_begin..precalculated_end])
+                        .for_each(|(sample, tail)| *sample += *tail);
                 }
-            }
 
-            // Sum: 2nd-Nth tail block
-            if self.tail_precalculated.len() > 0 {
-                let mut precalculated_pos = self.precalculated_pos;
-                for i in sum_begin..sum_end {
-                    output[i] += self.tail_precalculated[precalculated_pos];
-                    precalculated_pos += 1;
+                // Sum: 2nd-Nth tail block
+                if !self.tail_precalculated.is_empty() {
+                    output[sum_begin..sum_end]
+                        .iter_mut()
+                        .zip(&self.tail_precalculated[precalculated_begin..precalculated_end])
+                        .for_each(|(sample, tail)| *sample += *tail);
                 }
             }
 
@@ -465,34 +1183,99 @@ impl Convolution for TwoStageFFTConvolver {
             self.tail_input_fill += processing;
 
             // Convolution: 1st tail block
-            if self.tail_precalculated0.len() > 0 && self.tail_input_fill % HEAD_BLOCK_SIZE == 0 {
-                assert!(self.tail_input_fill >= HEAD_BLOCK_SIZE);
-                let block_offset = self.tail_input_fill - HEAD_BLOCK_SIZE;
-                self.tail_convolver0.process(
-                    &self.tail_input[block_offset..block_offset + HEAD_BLOCK_SIZE],
-                    &mut self.tail_output0[block_offset..block_offset + HEAD_BLOCK_SIZE],
-                );
-                if self.tail_input_fill == TAIL_BLOCK_SIZE {
+            if !self.tail_precalculated0.is_empty()
+                && self.tail_input_fill % self.head_block_size == 0
+            {
+                assert!(self.tail_input_fill >= self.head_block_size);
+                let block = self.tail_input_fill - self.head_block_size..self.tail_input_fill;
+                if output.is_some() {
+                    self.tail_convolver0.try_process(
+                        &self.tail_input[block.clone()],
+                        &mut self.tail_output0[block],
+                    )?;
+                } else {
+                    self.tail_convolver0
+                        .try_feed(&self.tail_input[block.clone()])?;
+                    self.tail_output0[block].fill(S::zero());
+                }
+                if self.tail_input_fill == self.tail_block_size {
                     std::mem::swap(&mut self.tail_precalculated0, &mut self.tail_output0);
                 }
             }
 
-            // Convolution: 2nd-Nth tail block (might be done in some background thread)
-            if self.tail_precalculated.len() > 0
-                && self.tail_input_fill == TAIL_BLOCK_SIZE
-                && self.tail_output.len() == TAIL_BLOCK_SIZE
+            // Convolution: 2nd-Nth tail block (optionally done in a background thread)
+            if !self.tail_precalculated.is_empty()
+                && self.tail_input_fill == self.tail_block_size
+                && self.tail_output.len() == self.tail_block_size
             {
-                std::mem::swap(&mut self.tail_precalculated, &mut self.tail_output);
-                self.tail_convolver
-                    .process(&self.tail_input, &mut self.tail_output);
+                match &mut self.tail_worker {
+                    Some(worker) => worker.exchange(&self.tail_input, &mut self.tail_precalculated),
+                    None => {
+                        std::mem::swap(&mut self.tail_precalculated, &mut self.tail_output);
+                        if output.is_some() {
+                            self.tail_convolver
+                                .try_process(&self.tail_input, &mut self.tail_output)?;
+                        } else {
+                            self.tail_convolver.try_feed(&self.tail_input)?;
+                            self.tail_output.fill(S::zero());
+                        }
+                    }
+                }
             }
 
-            if self.tail_input_fill == TAIL_BLOCK_SIZE {
+            if self.tail_input_fill == self.tail_block_size {
                 self.tail_input_fill = 0;
                 self.precalculated_pos = 0;
             }
 
             processed += processing;
         }
+
+        Ok(())
+    }
+
+    fn clear_tail_output(&mut self) {
+        self.tail_output0.fill(S::zero());
+        self.tail_precalculated0.fill(S::zero());
+        self.tail_output.fill(S::zero());
+        self.tail_precalculated.fill(S::zero());
+    }
+
+    fn clear_tail(&mut self) {
+        self.clear_tail_output();
+        self.tail_input.fill(S::zero());
+        self.tail_input_fill = 0;
+        self.precalculated_pos = 0;
+    }
+
+    // Drops the signal history of all stages after a failed FFT, see `FFTConvolver::recover`
+    fn recover(&mut self, output: &mut [S]) {
+        self.reset();
+        output.fill(S::zero());
+    }
+}
+
+#[test]
+fn test_two_stage_fft_convolver_failed_update_empties_all_stages() {
+    let block_size = 64;
+    let response: Vec<f32> = (0..3000).map(|i| 1.0 / (1 + i) as f32).collect();
+    let input = vec![1.0; 4 * block_size];
+    let mut convolver = TwoStageFFTConvolver::with_block_sizes(&response, 64, 256, 3000);
+    let mut output = vec![0.0; block_size];
+    convolver.process(&input[..block_size], &mut output);
+
+    // a plan of the wrong size makes the 1st tail stage fail after the head was updated
+    convolver.tail_convolver0.fft.init(block_size);
+    let result = convolver.try_update(&response[..1000]);
+    assert!(matches!(result, Err(ConvolutionError::Fft(_))));
+    convolver.tail_convolver0.fft.init(2 * block_size);
+
+    assert_eq!(convolver.response_length(), 0);
+    assert_eq!(convolver.head_convolver.response_length(), 0);
+    assert_eq!(convolver.tail_convolver0.response_length(), 0);
+    assert_eq!(convolver.tail_convolver.response_length(), 0);
+    for input_block in input.chunks_exact(block_size) {
+        convolver.process(input_block, &mut output);
+        assert!(output.iter().all(|sample| *sample == 0.0));
     }
 }
@@ -0,0 +1,460 @@
+use realfft::FftError;
+use rustfft::num_complex::Complex;
+
+use crate::crossfade_convolver::{
+    Crossfader, RaisedCosineMixer, Target, DEFAULT_CROSSFADE_SAMPLES,
+};
+use crate::fft_convolver::{
+    complex_multiply_accumulate, complex_size, copy_and_pad, multiply_partitions, sum, Fft,
+};
+use crate::partitioned_response::PartitionedResponse;
+use crate::split_complex::SplitSpectra;
+use crate::{BlockSizePolicy, Convolution, ConvolutionError, Sample};
+
+// Spectra of the input segments, shared by both responses
+#[derive(Clone)]
+struct DelayLine<S: Sample> {
+    segments: SplitSpectra<S>,
+    current: usize,
+    input_buffer: Vec<S>,
+    input_buffer_fill: usize,
+}
+
+// Everything that depends on one of the two responses
+#[derive(Clone)]
+struct Channel<S: Sample> {
+    response: PartitionedResponse<S>,
+    pre_multiplied: SplitSpectra<S>,
+    conv: SplitSpectra<S>,
+    overlap: Vec<S>,
+    output: Vec<S>,
+}
+
+/// Crossfades between two responses like the `CrossfadeConvolver`, but with a single
+/// uniformly partitioned convolution whose input spectra are shared by both responses.
+///
+/// Outside of a crossfade only the current response is convolved, which costs the same as an
+/// `FFTConvolver`. During a crossfade both responses are multiplied with the shared input
+/// spectra and transformed back, the forward FFT is only done once. As the new response is
+/// applied to the whole input history right away, it fades in without a hold period.
+#[derive(Clone)]
+pub struct FrequencyDomainCrossfadeConvolver<S: Sample = f32> {
+    block_size: usize,
+    max_response_length: usize,
+    fft: Fft<S>,
+    fft_buffer: Vec<S>,
+    spectrum: Vec<Complex<S>>,
+    delay_line: DelayLine<S>,
+    channel_a: Channel<S>,
+    channel_b: Channel<S>,
+    crossfader: Crossfader<RaisedCosineMixer, S>,
+    stored_response: Vec<S>,
+    stored_response_len: usize,
+    response_pending: bool,
+}
+
+impl<S: Sample> FrequencyDomainCrossfadeConvolver<S> {
+    /// Creates a convolver that fades between responses over `crossfade_samples` samples, the
+    /// partition size is derived from `block_size` according to `policy`.
+    pub fn try_new(
+        response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        crossfade_samples: usize,
+        policy: BlockSizePolicy,
+    ) -> Result<Self, ConvolutionError> {
+        if block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        let block_size = policy.partition_size(block_size);
+        let channel_a = Channel::try_new(response, block_size, max_response_length)?;
+        let channel_b = Channel::try_new(&[], block_size, max_response_length)?;
+
+        let mut fft = Fft::default();
+        fft.init(2 * block_size);
+        let seg_count = (max_response_length + block_size - 1) / block_size;
+
+        Ok(Self {
+            block_size,
+            max_response_length,
+            fft,
+            fft_buffer: vec![S::zero(); 2 * block_size],
+            spectrum: vec![Complex::new(S::zero(), S::zero()); complex_size(2 * block_size)],
+            delay_line: DelayLine {
+                segments: SplitSpectra::new(seg_count, complex_size(2 * block_size)),
+                current: 0,
+                input_buffer: vec![S::zero(); block_size],
+                input_buffer_fill: 0,
+            },
+            channel_a,
+            channel_b,
+            crossfader: Crossfader::new(RaisedCosineMixer, crossfade_samples.max(1), 0),
+            stored_response: vec![S::zero(); max_response_length],
+            stored_response_len: 0,
+            response_pending: false,
+        })
+    }
+
+    /// Like `try_new`, but panics on invalid arguments.
+    pub fn new(
+        response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        crossfade_samples: usize,
+        policy: BlockSizePolicy,
+    ) -> Self {
+        Self::try_new(
+            response,
+            block_size,
+            max_response_length,
+            crossfade_samples,
+            policy,
+        )
+        .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    pub fn is_crossfading(&self) -> bool {
+        self.crossfader.is_fading()
+    }
+
+    fn channel(&self, target: Target) -> &Channel<S> {
+        match target {
+            Target::A => &self.channel_a,
+            Target::B => &self.channel_b,
+        }
+    }
+
+    // Transforms the response into the idle channel and starts fading into it
+    fn start_crossfade(&mut self, response: &[S]) -> Result<(), FftError> {
+        let target = self.crossfader.target().other();
+        let channel = match target {
+            Target::A => &mut self.channel_a,
+            Target::B => &mut self.channel_b,
+        };
+        channel.response.transform(
+            response,
+            &self.fft,
+            &mut self.fft_buffer,
+            &mut self.spectrum,
+        )?;
+        channel.apply_history(
+            &self.delay_line,
+            &self.fft,
+            &mut self.fft_buffer,
+            &mut self.spectrum,
+        )?;
+        self.crossfader.fade_into(target);
+        Ok(())
+    }
+
+    fn process_blocks(&mut self, input: &[S], output: &mut [S]) -> Result<(), FftError> {
+        let block_size = self.block_size;
+        let mut processed = 0;
+        while processed < output.len() {
+            let delay_line = &mut self.delay_line;
+            let input_buffer_was_empty = delay_line.input_buffer_fill == 0;
+            let processing = std::cmp::min(
+                output.len() - processed,
+                block_size - delay_line.input_buffer_fill,
+            );
+            let input_buffer_pos = delay_line.input_buffer_fill;
+            let block_complete = input_buffer_pos + processing == block_size;
+
+            // Forward FFT, shared by both responses
+            delay_line.input_buffer[input_buffer_pos..input_buffer_pos + processing]
+                .copy_from_slice(&input[processed..processed + processing]);
+            copy_and_pad(&mut self.fft_buffer, &delay_line.input_buffer, block_size);
+            self.fft.forward(&mut self.fft_buffer, &mut self.spectrum)?;
+            delay_line
+                .segments
+                .store(delay_line.current, &self.spectrum);
+
+            let output = &mut output[processed..processed + processing];
+            let positions = input_buffer_pos..input_buffer_pos + processing;
+            if self.crossfader.is_fading() {
+                for channel in [&mut self.channel_a, &mut self.channel_b] {
+                    channel.process(
+                        &self.delay_line,
+                        input_buffer_was_empty,
+                        &self.fft,
+                        &mut self.fft_buffer,
+                        &mut self.spectrum,
+                    )?;
+                    channel.add_overlap(None, positions.clone(), &self.fft_buffer);
+                    if block_complete {
+                        channel.save_overlap(&self.fft_buffer);
+                    }
+                }
+                self.crossfader.mix_block(
+                    &self.channel_a.output[positions.clone()],
+                    &self.channel_b.output[positions],
+                    output,
+                );
+            } else {
+                let target = self.crossfader.target();
+                let channel = match target {
+                    Target::A => &mut self.channel_a,
+                    Target::B => &mut self.channel_b,
+                };
+                channel.process(
+                    &self.delay_line,
+                    input_buffer_was_empty,
+                    &self.fft,
+                    &mut self.fft_buffer,
+                    &mut self.spectrum,
+                )?;
+                channel.add_overlap(Some(output), positions, &self.fft_buffer);
+                if block_complete {
+                    channel.save_overlap(&self.fft_buffer);
+                }
+            }
+
+            // Input buffer full => Next block
+            let delay_line = &mut self.delay_line;
+            delay_line.input_buffer_fill += processing;
+            if block_complete {
+                delay_line.input_buffer.fill(S::zero());
+                delay_line.input_buffer_fill = 0;
+                delay_line.current = if delay_line.current > 0 {
+                    delay_line.current - 1
+                } else {
+                    delay_line.segments.count() - 1
+                };
+            }
+            processed += processing;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sample> Channel<S> {
+    fn try_new(
+        response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Ok(Self {
+            response: PartitionedResponse::try_with_capacity(
+                response,
+                block_size,
+                max_response_length,
+            )?,
+            pre_multiplied: SplitSpectra::new(1, complex_size(2 * block_size)),
+            conv: SplitSpectra::new(1, complex_size(2 * block_size)),
+            overlap: vec![S::zero(); block_size],
+            output: vec![S::zero(); block_size],
+        })
+    }
+
+    fn reset(&mut self) {
+        self.pre_multiplied.clear();
+        self.conv.clear();
+        self.overlap.fill(S::zero());
+        self.output.fill(S::zero());
+    }
+
+    // Convolves the current input segment, leaves the time domain result in `fft_buffer`
+    fn process(
+        &mut self,
+        delay_line: &DelayLine<S>,
+        input_buffer_was_empty: bool,
+        fft: &Fft<S>,
+        fft_buffer: &mut [S],
+        spectrum: &mut [Complex<S>],
+    ) -> Result<(), FftError> {
+        let partition_count = self.response.partition_count();
+        if input_buffer_was_empty {
+            self.pre_multiplied.clear();
+            multiply_partitions(
+                &mut self.pre_multiplied,
+                self.response.partitions(),
+                &delay_line.segments,
+                1..partition_count,
+                delay_line.current,
+            );
+        }
+        self.conv.copy_from(&self.pre_multiplied);
+        if partition_count > 0 {
+            complex_multiply_accumulate(
+                self.conv.spectra_mut(0..1),
+                delay_line
+                    .segments
+                    .spectra(delay_line.current..delay_line.current + 1),
+                self.response.partitions().spectra(0..1),
+            );
+        }
+        self.conv.load(0, spectrum);
+        fft.inverse(spectrum, fft_buffer)
+    }
+
+    // Adds the overlap of the previous block to the result of `process` and stores it in
+    // `output`, or in the channel's own buffer for mixing
+    fn add_overlap(
+        &mut self,
+        output: Option<&mut [S]>,
+        positions: std::ops::Range<usize>,
+        fft_buffer: &[S],
+    ) {
+        let output = match output {
+            Some(output) => output,
+            None => &mut self.output[positions.clone()],
+        };
+        sum(
+            output,
+            &fft_buffer[positions.clone()],
+            &self.overlap[positions],
+        );
+    }
+
+    fn save_overlap(&mut self, fft_buffer: &[S]) {
+        let block_size = self.overlap.len();
+        self.overlap
+            .copy_from_slice(&fft_buffer[block_size..2 * block_size]);
+    }
+
+    // Calculates the overlap of the previous block and the products of the preceding segments
+    // of the current block, as if the response had been in place all along
+    fn apply_history(
+        &mut self,
+        delay_line: &DelayLine<S>,
+        fft: &Fft<S>,
+        fft_buffer: &mut [S],
+        spectrum: &mut [Complex<S>],
+    ) -> Result<(), FftError> {
+        let partition_count = self.response.partition_count();
+        self.conv.clear();
+        multiply_partitions(
+            &mut self.conv,
+            self.response.partitions(),
+            &delay_line.segments,
+            0..partition_count,
+            delay_line.current + 1,
+        );
+        self.conv.load(0, spectrum);
+        fft.inverse(spectrum, fft_buffer)?;
+        self.save_overlap(fft_buffer);
+
+        if delay_line.input_buffer_fill > 0 {
+            self.pre_multiplied.clear();
+            multiply_partitions(
+                &mut self.pre_multiplied,
+                self.response.partitions(),
+                &delay_line.segments,
+                1..partition_count,
+                delay_line.current,
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sample> Convolution<S> for FrequencyDomainCrossfadeConvolver<S> {
+    /// Crossfades over `DEFAULT_CROSSFADE_SAMPLES`, use `try_new` to choose the crossfade length.
+    fn try_init(
+        response: &[S],
+        max_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Self::try_new(
+            response,
+            max_block_size,
+            max_response_length,
+            DEFAULT_CROSSFADE_SAMPLES,
+            BlockSizePolicy::RoundUp,
+        )
+    }
+
+    /// Starts a crossfade to `response`, or if a crossfade is running already, stores the
+    /// response to be faded to once it is complete. A stored response is replaced by later
+    /// updates.
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        if response.len() > self.max_response_length {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.max_response_length,
+            });
+        }
+
+        if !self.is_crossfading() {
+            self.response_pending = false;
+            return self.start_crossfade(response).map_err(Into::into);
+        }
+
+        self.stored_response[..response.len()].copy_from_slice(response);
+        self.stored_response_len = response.len();
+        self.response_pending = true;
+        Ok(())
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if input.len() != output.len() {
+            return Err(ConvolutionError::BufferSizeMismatch {
+                expected: output.len(),
+                actual: input.len(),
+            });
+        }
+
+        if !self.is_crossfading() && self.response_pending {
+            self.response_pending = false;
+            let stored_response = std::mem::take(&mut self.stored_response);
+            let result = self.start_crossfade(&stored_response[..self.stored_response_len]);
+            self.stored_response = stored_response;
+            if let Err(error) = result {
+                self.reset();
+                output.fill(S::zero());
+                return Err(error.into());
+            }
+        }
+
+        if let Err(error) = self.process_blocks(input, output) {
+            self.reset();
+            output.fill(S::zero());
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    /// Also completes a running crossfade, a pending response starts fading in with the next
+    /// processed block.
+    fn reset(&mut self) {
+        self.fft_buffer.fill(S::zero());
+        self.spectrum.fill(Complex::new(S::zero(), S::zero()));
+        self.delay_line.segments.clear();
+        self.delay_line.current = 0;
+        self.delay_line.input_buffer.fill(S::zero());
+        self.delay_line.input_buffer_fill = 0;
+        self.channel_a.reset();
+        self.channel_b.reset();
+        self.crossfader.reset();
+    }
+
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.max_response_length
+    }
+
+    /// Length of the response that is faded to, or was faded to last.
+    fn response_length(&self) -> usize {
+        self.channel(self.crossfader.target()).response.len()
+    }
+
+    /// While crossfading, the longer tail of both responses.
+    fn tail_length(&self) -> usize {
+        let length = if self.is_crossfading() {
+            self.channel_a
+                .response
+                .len()
+                .max(self.channel_b.response.len())
+        } else {
+            self.response_length()
+        };
+        length.saturating_sub(1)
+    }
+}
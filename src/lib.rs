@@ -1,15 +1,187 @@
+use realfft::num_traits::{Float, FloatConst, NumAssign};
+use realfft::{FftError, FftNum};
+
 pub mod crossfade_convolver;
 pub mod fft_convolver;
+pub mod frequency_domain_crossfade_convolver;
+pub mod multi_stage_convolver;
+pub mod partitioned_response;
+pub mod response_channel;
+pub mod scheduled_convolver;
+mod simd;
+pub mod split_complex;
+pub mod tail_worker;
+#[cfg(test)]
 mod tests;
 
-// todo: use a generic floating point type
-pub type Sample = f32;
+/// Floating point type the convolvers operate on, implemented for `f32` and `f64`.
+pub trait Sample: FftNum + Float + FloatConst + NumAssign + Default + simd::Kernels {}
+
+impl Sample for f32 {}
+impl Sample for f64 {}
+
+/// How a convolver derives its partition size from the block size of the host.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlockSizePolicy {
+    /// Rounds the block size up to the next power of two, which has the fastest FFTs. Host
+    /// blocks of other sizes are buffered and cost additional FFTs whenever they straddle a
+    /// partition boundary.
+    #[default]
+    RoundUp,
+    /// Uses the block size as it is with mixed-radix FFTs, e.g. for hosts running at 480 or
+    /// 960 samples per block.
+    Exact,
+}
+
+impl BlockSizePolicy {
+    pub(crate) fn partition_size(self, block_size: usize) -> usize {
+        match self {
+            Self::RoundUp => block_size.next_power_of_two(),
+            Self::Exact => block_size,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConvolutionError {
+    /// The response is longer than the maximum response length the convolver was created with.
+    ResponseTooLong { length: usize, max_length: usize },
+    /// A transformed response has no space for the maximum response length of the convolver.
+    InsufficientCapacity { capacity: usize, max_length: usize },
+    /// A block size of zero was requested.
+    ZeroBlockSize,
+    /// A block size does not meet the requirements of the convolver.
+    InvalidBlockSize(&'static str),
+    /// A buffer does not have the expected size.
+    BufferSizeMismatch { expected: usize, actual: usize },
+    /// The FFT failed.
+    Fft(FftError),
+    /// A queue between two threads has no space left.
+    QueueFull,
+    /// The response is shared with other convolvers and cannot be replaced in place.
+    SharedResponse,
+    /// A setting is out of range, e.g. a negative duration.
+    InvalidParameter(&'static str),
+    /// A worker thread could not be spawned.
+    ThreadSpawn(std::io::Error),
+}
+
+impl std::fmt::Display for ConvolutionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ResponseTooLong { length, max_length } => write!(
+                f,
+                "response length {length} exceeds the maximum response length {max_length}"
+            ),
+            Self::InsufficientCapacity {
+                capacity,
+                max_length,
+            } => write!(
+                f,
+                "response capacity {capacity} is below the maximum response length {max_length}"
+            ),
+            Self::ZeroBlockSize => write!(f, "block size must not be zero"),
+            Self::InvalidBlockSize(reason) => write!(f, "{reason}"),
+            Self::BufferSizeMismatch { expected, actual } => {
+                write!(f, "expected a buffer of size {expected}, got {actual}")
+            }
+            Self::Fft(error) => write!(f, "FFT failed: {error}"),
+            Self::QueueFull => write!(f, "queue is full"),
+            Self::SharedResponse => write!(f, "response is shared with other convolvers"),
+            Self::InvalidParameter(reason) => write!(f, "{reason}"),
+            Self::ThreadSpawn(error) => write!(f, "failed to spawn a worker thread: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ConvolutionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Fft(error) => Some(error),
+            Self::ThreadSpawn(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<FftError> for ConvolutionError {
+    fn from(error: FftError) -> Self {
+        Self::Fft(error)
+    }
+}
 
-pub trait Convolution: Clone {
-    fn init(response: &[Sample], max_block_size: usize, max_response_length: usize) -> Self;
+pub trait Convolution<S: Sample = f32>: Clone {
+    fn try_init(
+        response: &[S],
+        max_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError>;
 
     // must be implemented in a real-time safe way, e.g. no heap allocations
-    fn update(&mut self, response: &[Sample]);
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError>;
+
+    /// Convolves `input` into `output`, both need to have the same length.
+    ///
+    /// If processing fails, e.g. because of an FFT error, the output is silenced and the
+    /// convolver drops its signal history, so that the following blocks are processed from a
+    /// consistent state. The error is returned to the caller.
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError>;
+
+    /// Adds `input` to the signal history without calculating any output, e.g. to keep an
+    /// idle convolver in sync with the input at a fraction of the cost of `try_process`.
+    ///
+    /// The following output continues as if `input` had been processed, except for the part
+    /// of its convolution that rings into the following blocks, which is dropped like on an
+    /// update with `UpdateMode::DropOverlap`. The default implementation processes the input
+    /// and discards the output, which keeps that part as well. Errors are handled like in
+    /// `try_process`.
+    fn try_feed(&mut self, input: &[S]) -> Result<(), ConvolutionError> {
+        let mut output = [S::zero(); 64];
+        for input in input.chunks(output.len()) {
+            self.try_process(input, &mut output[..input.len()])?;
+        }
+        Ok(())
+    }
+
+    /// Drops the signal history, so that the output does not ring on after e.g. a transport
+    /// stop. The response stays in place and does not need to be transformed again, this is
+    /// real-time safe.
+    fn reset(&mut self);
+
+    /// Latency added to the signal in samples.
+    fn latency(&self) -> usize;
+
+    /// Block size the response is partitioned with, for non-uniform convolvers the smallest one.
+    fn block_size(&self) -> usize;
+
+    /// Maximum length of a response accepted by `try_update`.
+    fn max_response_length(&self) -> usize;
+
+    /// Length of the currently active response.
+    fn response_length(&self) -> usize;
+
+    /// Number of samples the output keeps ringing after the input went silent.
+    fn tail_length(&self) -> usize {
+        self.response_length().saturating_sub(1)
+    }
+
+    /// Like `try_init`, but panics on invalid arguments.
+    fn init(response: &[S], max_block_size: usize, max_response_length: usize) -> Self {
+        Self::try_init(response, max_block_size, max_response_length)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Like `try_update`, but panics if the response is rejected.
+    fn update(&mut self, response: &[S]) {
+        if let Err(error) = self.try_update(response) {
+            panic!("{error}");
+        }
+    }
 
-    fn process(&mut self, input: &[Sample], output: &mut [Sample]);
+    /// Like `try_process`, but ignores errors, the output is silent for a failed block.
+    fn process(&mut self, input: &[S], output: &mut [S]) {
+        if self.try_process(input, output).is_err() {
+            output.fill(S::zero());
+        }
+    }
 }
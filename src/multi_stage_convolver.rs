@@ -0,0 +1,354 @@
+use crate::fft_convolver::FFTConvolver;
+use crate::{BlockSizePolicy, Convolution, ConvolutionError, Sample};
+
+/// A single tail stage, convolving a section of the response at its own block size.
+///
+/// The stage collects `block_size` input samples before it convolves them, the result is
+/// then played back during the following `block_size` samples. The section of the response
+/// handled by the stage therefore has to start at `block_size` at the earliest.
+#[derive(Clone)]
+struct Stage<S: Sample> {
+    block_size: usize,
+    offset: usize,
+    convolver: FFTConvolver<S>,
+    input: Vec<S>,
+    precalculated: Vec<S>,
+}
+
+/// Non-uniformly partitioned convolution with an arbitrary number of stages.
+///
+/// The response is split into sections of increasing partition size (Gardner/Garcia style),
+/// e.g. for block sizes `[64, 256, 1024, 8192]`:
+///
+/// - `[0, 256)` is convolved with 64 sample partitions (head)
+/// - `[256, 1024)` is convolved with 256 sample partitions
+/// - `[1024, 8192)` is convolved with 1024 sample partitions
+/// - `[8192, ..)` is convolved with 8192 sample partitions
+///
+/// The head is processed like a plain `FFTConvolver` and does not add any latency, each tail
+/// stage is fed at its own rate and its output is delayed by exactly its block size, which
+/// equals the offset of its section in the response.
+#[derive(Clone)]
+pub struct MultiStageFFTConvolver<S: Sample = f32> {
+    ir_len: usize,
+    response_len: usize,
+    block_sizes: Vec<usize>,
+    head_convolver: FFTConvolver<S>,
+    stages: Vec<Stage<S>>,
+    position: usize,
+}
+
+const MAX_DEFAULT_BLOCK_SIZE: usize = 8192;
+const STAGE_GROWTH_FACTOR: usize = 4;
+
+impl<S: Sample> MultiStageFFTConvolver<S> {
+    /// Creates a convolver with explicit partition sizes, starting with the head.
+    ///
+    /// Each block size must be a multiple of the previous one and larger than it. Stages starting
+    /// beyond `max_response_length` are omitted.
+    pub fn try_with_block_sizes(
+        impulse_response: &[S],
+        block_sizes: &[usize],
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        if block_sizes.is_empty() {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "at least one block size is required",
+            ));
+        }
+        if block_sizes.contains(&0) {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        if block_sizes.windows(2).any(|sizes| sizes[1] <= sizes[0]) {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "block sizes must be strictly increasing",
+            ));
+        }
+        // Every stage has to complete its blocks at the boundaries of the previous stage
+        if block_sizes.windows(2).any(|sizes| sizes[1] % sizes[0] != 0) {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "each block size must be a multiple of the previous one",
+            ));
+        }
+        if max_response_length < impulse_response.len() {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: impulse_response.len(),
+                max_length: max_response_length,
+            });
+        }
+        let mut padded_ir = impulse_response.to_vec();
+        padded_ir.resize(max_response_length, S::zero());
+
+        let section_end = |index: usize| {
+            block_sizes
+                .get(index + 1)
+                .map_or(max_response_length, |&end| end.min(max_response_length))
+        };
+
+        let head_ir_len = section_end(0);
+        let head_convolver = FFTConvolver::try_with_block_size_policy(
+            &padded_ir[0..head_ir_len],
+            block_sizes[0],
+            head_ir_len,
+            BlockSizePolicy::Exact,
+        )?;
+
+        let stages = block_sizes
+            .iter()
+            .enumerate()
+            .skip(1)
+            .take_while(|(_, &block_size)| block_size < max_response_length)
+            .map(|(index, &block_size)| {
+                let offset = block_size;
+                let end = section_end(index);
+                Ok(Stage {
+                    block_size,
+                    offset,
+                    convolver: FFTConvolver::try_with_block_size_policy(
+                        &padded_ir[offset..end],
+                        block_size,
+                        end - offset,
+                        BlockSizePolicy::Exact,
+                    )?,
+                    input: vec![S::zero(); block_size],
+                    precalculated: vec![S::zero(); block_size],
+                })
+            })
+            .collect::<Result<_, ConvolutionError>>()?;
+
+        Ok(Self {
+            ir_len: max_response_length,
+            response_len: impulse_response.len(),
+            block_sizes: block_sizes.to_vec(),
+            head_convolver,
+            stages,
+            position: 0,
+        })
+    }
+
+    /// Like `try_with_block_sizes`, but panics on invalid arguments.
+    pub fn with_block_sizes(
+        impulse_response: &[S],
+        block_sizes: &[usize],
+        max_response_length: usize,
+    ) -> Self {
+        Self::try_with_block_sizes(impulse_response, block_sizes, max_response_length)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Derives the partition sizes from the host block size: the head uses `block_size`
+    /// according to `policy`, each further stage is up to four times larger than the previous
+    /// one, up to 8192 samples or until the response is covered.
+    pub fn try_with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Result<Self, ConvolutionError> {
+        if block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        let mut block_sizes = vec![policy.partition_size(block_size)];
+        loop {
+            let last = block_sizes[block_sizes.len() - 1];
+            if last >= max_response_length {
+                break;
+            }
+            // The next stage has to be a multiple of the previous one
+            let growth_factor = std::cmp::min(STAGE_GROWTH_FACTOR, MAX_DEFAULT_BLOCK_SIZE / last);
+            if growth_factor < 2 {
+                break;
+            }
+            block_sizes.push(growth_factor * last);
+        }
+        Self::try_with_block_sizes(impulse_response, &block_sizes, max_response_length)
+    }
+
+    /// Like `try_with_block_size_policy`, but panics on invalid arguments.
+    pub fn with_block_size_policy(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+        policy: BlockSizePolicy,
+    ) -> Self {
+        Self::try_with_block_size_policy(impulse_response, block_size, max_response_length, policy)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Partition sizes of all stages, starting with the head.
+    pub fn block_sizes(&self) -> &[usize] {
+        &self.block_sizes
+    }
+}
+
+impl<S: Sample> Convolution<S> for MultiStageFFTConvolver<S> {
+    /// See `try_with_block_size_policy`, the block size is rounded up to a power of two.
+    fn try_init(
+        impulse_response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Self::try_with_block_size_policy(
+            impulse_response,
+            block_size,
+            max_response_length,
+            BlockSizePolicy::RoundUp,
+        )
+    }
+
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        let new_ir_len = response.len();
+
+        if new_ir_len > self.ir_len {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: new_ir_len,
+                max_length: self.ir_len,
+            });
+        }
+
+        let head_end = self
+            .stages
+            .first()
+            .map_or(new_ir_len, |stage| stage.offset.min(new_ir_len));
+        self.head_convolver.try_update(&response[0..head_end])?;
+
+        for index in 0..self.stages.len() {
+            let begin = self.stages[index].offset.min(new_ir_len);
+            let end = self
+                .stages
+                .get(index + 1)
+                .map_or(new_ir_len, |stage| stage.offset.min(new_ir_len));
+            // The precalculated output belongs to the previous response, the input is kept as
+            // part of the signal history
+            let stage = &mut self.stages[index];
+            stage.convolver.try_update(&response[begin..end])?;
+            stage.precalculated.fill(S::zero());
+        }
+
+        self.response_len = new_ir_len;
+
+        Ok(())
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if let Err(error) = self.process_stages(input, Some(&mut *output)) {
+            self.recover(output);
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// Feeds every stage, the output the tail stages precalculate for the fed input is dropped.
+    fn try_feed(&mut self, input: &[S]) -> Result<(), ConvolutionError> {
+        if let Err(error) = self.process_stages(input, None) {
+            self.reset();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    // Each tail stage is delayed by exactly the offset of its section, only the head determines
+    // the latency
+    fn latency(&self) -> usize {
+        0
+    }
+
+    fn block_size(&self) -> usize {
+        self.block_sizes[0]
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.ir_len
+    }
+
+    fn response_length(&self) -> usize {
+        self.response_len
+    }
+
+    fn reset(&mut self) {
+        self.head_convolver.reset();
+        for stage in self.stages.iter_mut() {
+            stage.convolver.reset();
+            stage.input.fill(S::zero());
+            stage.precalculated.fill(S::zero());
+        }
+        self.position = 0;
+    }
+}
+
+impl<S: Sample> MultiStageFFTConvolver<S> {
+    // Without an output the stages are only fed
+    fn process_stages(
+        &mut self,
+        input: &[S],
+        mut output: Option<&mut [S]>,
+    ) -> Result<(), ConvolutionError> {
+        // Head
+        match output.as_deref_mut() {
+            Some(output) => self.head_convolver.try_process(input, output)?,
+            None => self.head_convolver.try_feed(input)?,
+        }
+
+        // Tail
+        let Some(first_stage) = self.stages.first() else {
+            return Ok(());
+        };
+        let min_block_size = first_stage.block_size;
+
+        let len = input.len();
+        let mut processed = 0;
+
+        while processed < len {
+            let processing = std::cmp::min(
+                len - processed,
+                min_block_size - (self.position % min_block_size),
+            );
+            let mut output = output
+                .as_deref_mut()
+                .map(|output| &mut output[processed..processed + processing]);
+            let input = &input[processed..processed + processing];
+
+            for stage in self.stages.iter_mut() {
+                let begin = self.position % stage.block_size;
+                let end = begin + processing;
+
+                if let Some(output) = output.as_deref_mut() {
+                    output
+                        .iter_mut()
+                        .zip(&stage.precalculated[begin..end])
+                        .for_each(|(sample, tail)| *sample += *tail);
+                }
+                stage.input[begin..end].copy_from_slice(input);
+
+                // Input block complete => precalculate the output for the next block
+                if end == stage.block_size {
+                    if output.is_some() {
+                        stage
+                            .convolver
+                            .try_process(&stage.input, &mut stage.precalculated)?;
+                    } else {
+                        stage.convolver.try_feed(&stage.input)?;
+                        stage.precalculated.fill(S::zero());
+                    }
+                }
+            }
+
+            self.position += processing;
+            if let Some(last_stage) = self.stages.last() {
+                if self.position == last_stage.block_size {
+                    self.position = 0;
+                }
+            }
+
+            processed += processing;
+        }
+
+        Ok(())
+    }
+
+    // Drops the signal history of all stages after a failed FFT, see `FFTConvolver::recover`.
+    fn recover(&mut self, output: &mut [S]) {
+        self.reset();
+        output.fill(S::zero());
+    }
+}
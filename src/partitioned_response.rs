@@ -0,0 +1,164 @@
+use realfft::FftError;
+use rustfft::num_complex::Complex;
+
+use crate::fft_convolver::{complex_size, copy_and_pad, Fft};
+use crate::split_complex::SplitSpectra;
+use crate::{ConvolutionError, Sample};
+
+/// A response split into partitions of `block_size` samples, each of them transformed into
+/// the frequency domain.
+///
+/// Transforming a response is the expensive part of setting up an `FFTConvolver`. A
+/// `PartitionedResponse` can be created once off the audio thread and shared through an `Arc`
+/// between any number of convolvers with the same block size, see
+/// `FFTConvolver::try_with_response` and `FFTConvolver::set_response`.
+#[derive(Clone, Debug, Default)]
+pub struct PartitionedResponse<S: Sample = f32> {
+    block_size: usize,
+    len: usize,
+    partition_count: usize,
+    partitions: SplitSpectra<S>,
+}
+
+impl<S: Sample> PartitionedResponse<S> {
+    /// Partitions and transforms `response`, `block_size` has to match the block size of the
+    /// convolvers the response is used with (see `Convolution::block_size`).
+    pub fn try_new(response: &[S], block_size: usize) -> Result<Self, ConvolutionError> {
+        Self::try_with_capacity(response, block_size, response.len())
+    }
+
+    /// Like `try_new`, but panics on invalid arguments.
+    pub fn new(response: &[S], block_size: usize) -> Self {
+        Self::try_new(response, block_size).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Reserves space for responses of up to `max_response_length` samples, so that the
+    /// response can be replaced without allocating.
+    ///
+    /// Responses passed to `FFTConvolver::set_response` need space for the maximum response
+    /// length of the convolver, as the convolver transforms later updates into them.
+    pub fn try_with_capacity(
+        response: &[S],
+        block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        if block_size == 0 {
+            return Err(ConvolutionError::ZeroBlockSize);
+        }
+        if max_response_length < response.len() {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: max_response_length,
+            });
+        }
+
+        let mut fft = Fft::default();
+        fft.init(2 * block_size);
+        let mut fft_buffer = vec![S::zero(); 2 * block_size];
+        let mut spectrum = vec![Complex::new(S::zero(), S::zero()); complex_size(2 * block_size)];
+
+        let mut partitioned_response = Self {
+            block_size,
+            len: 0,
+            partition_count: 0,
+            partitions: SplitSpectra::new(
+                partition_count(max_response_length, block_size),
+                complex_size(2 * block_size),
+            ),
+        };
+        partitioned_response.transform(response, &fft, &mut fft_buffer, &mut spectrum)?;
+        Ok(partitioned_response)
+    }
+
+    /// Like `try_with_capacity`, but panics on invalid arguments.
+    pub fn with_capacity(response: &[S], block_size: usize, max_response_length: usize) -> Self {
+        Self::try_with_capacity(response, block_size, max_response_length)
+            .unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /// Partition size in samples.
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Length of the response in samples.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Length in samples the response has space for without allocating.
+    pub fn capacity(&self) -> usize {
+        self.partitions.count() * self.block_size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of partitions covering the response.
+    pub fn partition_count(&self) -> usize {
+        self.partition_count
+    }
+
+    pub(crate) fn partitions(&self) -> &SplitSpectra<S> {
+        &self.partitions
+    }
+
+    /// Replaces the response, only allocates if it has more partitions than there is space for.
+    ///
+    /// `fft`, `fft_buffer` and `spectrum` have to be set up for a block size of
+    /// `2 * block_size`. If the FFT fails, the response is left empty.
+    pub(crate) fn transform(
+        &mut self,
+        response: &[S],
+        fft: &Fft<S>,
+        fft_buffer: &mut [S],
+        spectrum: &mut [Complex<S>],
+    ) -> Result<(), FftError> {
+        self.clear(response.len());
+        for index in 0..partition_count(response.len(), self.block_size) {
+            self.transform_partition(index, response, fft, fft_buffer, spectrum)?;
+        }
+        self.finish(response.len());
+
+        Ok(())
+    }
+
+    /// Empties the response and makes space for a response of `length` samples, which is then
+    /// transformed partition by partition with `transform_partition` and completed by `finish`.
+    pub(crate) fn clear(&mut self, length: usize) {
+        let count = partition_count(length, self.block_size);
+        if count > self.partitions.count() {
+            self.partitions = SplitSpectra::new(count, self.partitions.bins());
+        }
+        self.len = 0;
+        self.partition_count = 0;
+    }
+
+    /// Transforms the partition at `index` of `response`, see `transform`.
+    pub(crate) fn transform_partition(
+        &mut self,
+        index: usize,
+        response: &[S],
+        fft: &Fft<S>,
+        fft_buffer: &mut [S],
+        spectrum: &mut [Complex<S>],
+    ) -> Result<(), FftError> {
+        let start = index * self.block_size;
+        let end = response.len().min(start + self.block_size);
+        copy_and_pad(fft_buffer, &response[start..end], end - start);
+        fft.forward(fft_buffer, spectrum)?;
+        self.partitions.store(index, spectrum);
+        Ok(())
+    }
+
+    /// Completes a response of `length` samples after all of its partitions were transformed.
+    pub(crate) fn finish(&mut self, length: usize) {
+        self.len = length;
+        self.partition_count = partition_count(length, self.block_size);
+    }
+}
+
+pub(crate) fn partition_count(length: usize, block_size: usize) -> usize {
+    (length + block_size - 1) / block_size
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::fft_convolver::FFTConvolver;
+use crate::partitioned_response::PartitionedResponse;
+use crate::{Convolution, ConvolutionError, Sample};
+
+/// Creates a channel for handing responses from a control thread to the `FFTConvolver`
+/// `convolver` running on the audio thread.
+///
+/// Up to `capacity` responses can be pending at a time. The responses are transformed by the
+/// sender, the receiver only swaps them in (see `FFTConvolver::set_response`) and passes the
+/// previous response back, so that it is released on the control thread as well.
+pub fn response_channel<S: Sample>(
+    convolver: &FFTConvolver<S>,
+    capacity: usize,
+) -> (ResponseSender<S>, ResponseReceiver<S>) {
+    let (pending, receiver_pending) = RingBuffer::new(capacity);
+    // Every response sent leads to exactly one retired response, either itself if a newer
+    // response arrived before it was picked up, or the response it replaced. As the sender
+    // collects the retired responses before sending, one more slot than pending responses is
+    // enough for the receiver to never wait for the sender.
+    let (receiver_retired, retired) = RingBuffer::new(capacity + 1);
+
+    let sender = ResponseSender {
+        block_size: convolver.block_size(),
+        max_response_length: convolver.max_response_length(),
+        pending,
+        retired,
+    };
+    let receiver = ResponseReceiver {
+        pending: receiver_pending,
+        retired: receiver_retired,
+    };
+    (sender, receiver)
+}
+
+/// Control thread end of a `response_channel`.
+pub struct ResponseSender<S: Sample = f32> {
+    block_size: usize,
+    max_response_length: usize,
+    pending: Producer<Arc<PartitionedResponse<S>>>,
+    retired: Consumer<Arc<PartitionedResponse<S>>>,
+}
+
+/// Audio thread end of a `response_channel`.
+pub struct ResponseReceiver<S: Sample = f32> {
+    pending: Consumer<Arc<PartitionedResponse<S>>>,
+    retired: Producer<Arc<PartitionedResponse<S>>>,
+}
+
+impl<S: Sample> ResponseSender<S> {
+    /// Transforms `response` and posts it to the receiver.
+    ///
+    /// This allocates and runs the FFTs, call it off the audio thread.
+    pub fn try_send_response(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        if response.len() > self.max_response_length {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.max_response_length,
+            });
+        }
+        let response = PartitionedResponse::try_with_capacity(
+            response,
+            self.block_size,
+            self.max_response_length,
+        )?;
+        self.try_send(Arc::new(response))
+    }
+
+    /// Like `try_send_response`, but panics on invalid arguments or if the channel is full.
+    pub fn send_response(&mut self, response: &[S]) {
+        self.try_send_response(response)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    /// Posts an already transformed response, e.g. one shared with other convolvers.
+    ///
+    /// The response needs space for the maximum response length of the convolver, see
+    /// `PartitionedResponse::try_with_capacity`.
+    ///
+    /// Releases the responses retired by the receiver before, see `collect`.
+    pub fn try_send(
+        &mut self,
+        response: Arc<PartitionedResponse<S>>,
+    ) -> Result<(), ConvolutionError> {
+        if response.block_size() != self.block_size {
+            return Err(ConvolutionError::InvalidBlockSize(
+                "the response is partitioned with a different block size",
+            ));
+        }
+        if response.len() > self.max_response_length {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.max_response_length,
+            });
+        }
+        if response.capacity() < self.max_response_length {
+            return Err(ConvolutionError::InsufficientCapacity {
+                capacity: response.capacity(),
+                max_length: self.max_response_length,
+            });
+        }
+
+        self.collect();
+        self.pending
+            .push(response)
+            .map_err(|_| ConvolutionError::QueueFull)
+    }
+
+    /// Releases the responses the receiver has replaced so far and returns their number.
+    ///
+    /// This happens on every send, call it periodically to free the memory of the previous
+    /// response without sending a new one.
+    pub fn collect(&mut self) -> usize {
+        let mut count = 0;
+        while self.retired.pop().is_ok() {
+            count += 1;
+        }
+        count
+    }
+
+    /// Number of responses that have not been picked up by the receiver yet.
+    pub fn pending(&self) -> usize {
+        self.pending.buffer().capacity() - self.pending.slots()
+    }
+}
+
+impl<S: Sample> ResponseReceiver<S> {
+    /// Swaps the most recent pending response into `convolver`, call it between two blocks.
+    ///
+    /// Returns whether the response was replaced, the signal history is handled like on
+    /// `update`.
+    /// Responses superseded by a newer one are skipped. This neither allocates nor frees memory
+    /// and is real-time safe.
+    pub fn receive(&mut self, convolver: &mut FFTConvolver<S>) -> bool {
+        let mut received = false;
+        while self.retired.slots() > 0 {
+            let Ok(response) = self.pending.pop() else {
+                break;
+            };
+            if !self.pending.is_empty() {
+                // A newer response is waiting already
+                let _ = self.retired.push(response);
+                continue;
+            }
+            // Either the previous response or, if the sender checked it against a different
+            // convolver, the rejected one is handed back, both are released by the sender
+            let mut response = response;
+            if convolver.set_response(&mut response).is_ok() {
+                received = true;
+            }
+            let _ = self.retired.push(response);
+        }
+        received
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{Convolution, ConvolutionError, Sample};
+
+/// Wraps a convolver to switch responses at an exact sample position instead of the start of
+/// the next processed block.
+///
+/// The wrapper counts the processed samples. A scheduled update splits the block it falls
+/// into, the part before the update is processed with the previous response and the rest with
+/// the new one (or, for a `CrossfadeConvolver`, the crossfade starts exactly there).
+#[derive(Clone)]
+pub struct ScheduledConvolver<Convolver: Convolution<S>, S: Sample = f32> {
+    convolver: Convolver,
+    position: u64,
+    scheduled_response: Vec<S>,
+    scheduled_response_len: usize,
+    scheduled_time: Option<u64>,
+}
+
+impl<Convolver: Convolution<S>, S: Sample> ScheduledConvolver<Convolver, S> {
+    pub fn new(convolver: Convolver) -> Self {
+        Self {
+            scheduled_response: vec![S::zero(); convolver.max_response_length()],
+            convolver,
+            position: 0,
+            scheduled_response_len: 0,
+            scheduled_time: None,
+        }
+    }
+
+    pub fn convolver(&self) -> &Convolver {
+        &self.convolver
+    }
+
+    pub fn convolver_mut(&mut self) -> &mut Convolver {
+        &mut self.convolver
+    }
+
+    /// Number of samples processed so far, the time base of `try_schedule_update`.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Moves the time base, e.g. to follow the transport of the host.
+    pub fn set_position(&mut self, position: u64) {
+        self.position = position;
+    }
+
+    /// Replaces the response once `time` samples have been processed, see `position`.
+    ///
+    /// If `time` has passed already, the response is replaced at the start of the next block.
+    /// Only one update can be scheduled, scheduling another one replaces it. This copies the
+    /// response and is real-time safe, the update itself happens during `process`.
+    pub fn try_schedule_update(
+        &mut self,
+        response: &[S],
+        time: u64,
+    ) -> Result<(), ConvolutionError> {
+        if response.len() > self.scheduled_response.len() {
+            return Err(ConvolutionError::ResponseTooLong {
+                length: response.len(),
+                max_length: self.scheduled_response.len(),
+            });
+        }
+
+        self.scheduled_response[..response.len()].copy_from_slice(response);
+        self.scheduled_response_len = response.len();
+        self.scheduled_time = Some(time);
+        Ok(())
+    }
+
+    /// Like `try_schedule_update`, but panics on invalid arguments.
+    pub fn schedule_update(&mut self, response: &[S], time: u64) {
+        self.try_schedule_update(response, time)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    /// Replaces the response `offset` samples into the next processed block.
+    pub fn try_schedule_update_in(
+        &mut self,
+        response: &[S],
+        offset: usize,
+    ) -> Result<(), ConvolutionError> {
+        self.try_schedule_update(response, self.position + offset as u64)
+    }
+
+    /// Like `try_schedule_update_in`, but panics on invalid arguments.
+    pub fn schedule_update_in(&mut self, response: &[S], offset: usize) {
+        self.try_schedule_update_in(response, offset)
+            .unwrap_or_else(|error| panic!("{error}"));
+    }
+
+    /// Time of the scheduled update, if there is one.
+    pub fn scheduled_time(&self) -> Option<u64> {
+        self.scheduled_time
+    }
+
+    pub fn cancel_scheduled_update(&mut self) {
+        self.scheduled_time = None;
+    }
+
+    fn apply_scheduled_update(&mut self) -> Result<(), ConvolutionError> {
+        self.scheduled_time = None;
+        self.convolver
+            .try_update(&self.scheduled_response[..self.scheduled_response_len])
+    }
+}
+
+impl<Convolver: Convolution<S>, S: Sample> Convolution<S> for ScheduledConvolver<Convolver, S> {
+    fn try_init(
+        response: &[S],
+        max_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Ok(Self::new(Convolver::try_init(
+            response,
+            max_block_size,
+            max_response_length,
+        )?))
+    }
+
+    /// Replaces the response right away, a scheduled update stays scheduled.
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        self.convolver.try_update(response)
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        if input.len() != output.len() {
+            return Err(ConvolutionError::BufferSizeMismatch {
+                expected: output.len(),
+                actual: input.len(),
+            });
+        }
+
+        // A failed update keeps the previous response, the error is returned after the block
+        // has been processed
+        let mut result = Ok(());
+        let mut processed = 0;
+        while processed < output.len() {
+            let end = match self.scheduled_time {
+                Some(time) if time <= self.position + processed as u64 => {
+                    result = result.and(self.apply_scheduled_update());
+                    continue;
+                }
+                Some(time) => (time - self.position).min(output.len() as u64) as usize,
+                None => output.len(),
+            };
+            result = result.and(
+                self.convolver
+                    .try_process(&input[processed..end], &mut output[processed..end]),
+            );
+            processed = end;
+        }
+        self.position += output.len() as u64;
+
+        result
+    }
+
+    fn reset(&mut self) {
+        self.convolver.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.convolver.latency()
+    }
+
+    fn block_size(&self) -> usize {
+        self.convolver.block_size()
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.convolver.max_response_length()
+    }
+
+    fn response_length(&self) -> usize {
+        self.convolver.response_length()
+    }
+
+    fn tail_length(&self) -> usize {
+        self.convolver.tail_length()
+    }
+}
@@ -0,0 +1,548 @@
+//! Vectorized implementations of the inner loops of the `FFTConvolver`.
+//!
+//! The instruction set is detected at runtime, the scalar implementation is used on other
+//! architectures or if no supported extension is available.
+
+use crate::split_complex::{SplitSpectraMut, SplitSpectraRef};
+use crate::Sample;
+
+/// Instruction set extension an implementation is written for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstructionSet {
+    Scalar,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Sse2,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Avx,
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    Avx2Fma,
+}
+
+impl InstructionSet {
+    /// The fastest instruction set supported by the CPU.
+    pub fn detect() -> Self {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                return Self::Avx2Fma;
+            }
+            if is_x86_feature_detected!("avx") {
+                return Self::Avx;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Self::Sse2;
+            }
+        }
+        Self::Scalar
+    }
+
+    #[cfg(test)]
+    fn supported() -> Vec<Self> {
+        let mut supported = vec![Self::Scalar];
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                supported.push(Self::Sse2);
+            }
+            if is_x86_feature_detected!("avx") {
+                supported.push(Self::Avx);
+            }
+            if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma") {
+                supported.push(Self::Avx2Fma);
+            }
+        }
+        supported
+    }
+}
+
+/// Inner loops of the convolution, specialized for each sample type.
+///
+/// This trait is a supertrait of `Sample` and can not be implemented outside of the crate.
+pub trait Kernels: Sized {
+    /// Accumulates the products of the spectra in `a` and `b` into `result` with
+    /// `instruction_set`.
+    ///
+    /// # Safety
+    ///
+    /// `instruction_set` must be supported by the CPU, e.g. the one returned by
+    /// `InstructionSet::detect`.
+    unsafe fn complex_multiply_accumulate_with(
+        instruction_set: InstructionSet,
+        result: SplitSpectraMut<Self>,
+        a: SplitSpectraRef<Self>,
+        b: SplitSpectraRef<Self>,
+    );
+
+    /// Stores the sum of `a` and `b` in `result` with `instruction_set`.
+    ///
+    /// # Safety
+    ///
+    /// `instruction_set` must be supported by the CPU, e.g. the one returned by
+    /// `InstructionSet::detect`.
+    unsafe fn sum_with(
+        instruction_set: InstructionSet,
+        result: &mut [Self],
+        a: &[Self],
+        b: &[Self],
+    );
+
+    fn complex_multiply_accumulate(
+        result: SplitSpectraMut<Self>,
+        a: SplitSpectraRef<Self>,
+        b: SplitSpectraRef<Self>,
+    ) {
+        // Safety: the detected instruction set is supported
+        unsafe {
+            Self::complex_multiply_accumulate_with(InstructionSet::detect(), result, a, b);
+        }
+    }
+
+    fn sum(result: &mut [Self], a: &[Self], b: &[Self]) {
+        // Safety: the detected instruction set is supported
+        unsafe { Self::sum_with(InstructionSet::detect(), result, a, b) }
+    }
+}
+
+macro_rules! impl_kernels {
+    ($sample:ty, $complex_multiply_accumulate:ident, $sum:ident) => {
+        impl Kernels for $sample {
+            unsafe fn complex_multiply_accumulate_with(
+                instruction_set: InstructionSet,
+                mut result: SplitSpectraMut<Self>,
+                a: SplitSpectraRef<Self>,
+                b: SplitSpectraRef<Self>,
+            ) {
+                let padded_bins = result.padded_bins();
+                assert_eq!(result.count(), 1);
+                assert_eq!(a.count(), b.count());
+                assert_eq!(a.padded_bins(), padded_bins);
+                assert_eq!(b.padded_bins(), padded_bins);
+                let (result, a, b) = (result.data(), a.data(), b.data());
+                match instruction_set {
+                    InstructionSet::Scalar => {
+                        scalar_complex_multiply_accumulate(result, a, b, padded_bins)
+                    }
+                    // Safety: the caller guarantees that the instruction set is supported, the
+                    // dimensions of the spectra have been checked above
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    instruction_set => unsafe {
+                        x86::$complex_multiply_accumulate(
+                            instruction_set,
+                            result,
+                            a,
+                            b,
+                            padded_bins,
+                        )
+                    },
+                }
+            }
+
+            unsafe fn sum_with(
+                instruction_set: InstructionSet,
+                result: &mut [Self],
+                a: &[Self],
+                b: &[Self],
+            ) {
+                assert_eq!(result.len(), a.len());
+                assert_eq!(result.len(), b.len());
+                match instruction_set {
+                    InstructionSet::Scalar => scalar_sum(result, a, b),
+                    // Safety: see above
+                    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+                    instruction_set => unsafe { x86::$sum(instruction_set, result, a, b) },
+                }
+            }
+        }
+    };
+}
+
+impl_kernels!(f32, complex_multiply_accumulate_f32, sum_f32);
+impl_kernels!(f64, complex_multiply_accumulate_f64, sum_f64);
+
+// The spectra are passed as their padded data, see `SplitSpectra`: `result` is a single
+// spectrum, `a` and `b` contain the same number of spectra.
+fn scalar_complex_multiply_accumulate<S: Sample>(
+    result: &mut [S],
+    a: &[S],
+    b: &[S],
+    padded_bins: usize,
+) {
+    let (result_re, result_im) = result.split_at_mut(padded_bins);
+    let spectra = a
+        .chunks_exact(2 * padded_bins)
+        .zip(b.chunks_exact(2 * padded_bins));
+    for (a, b) in spectra {
+        let (a_re, a_im) = a.split_at(padded_bins);
+        let (b_re, b_im) = b.split_at(padded_bins);
+        let result = result_re.iter_mut().zip(result_im.iter_mut());
+        let a = a_re.iter().zip(a_im);
+        let b = b_re.iter().zip(b_im);
+        for (((re, im), (a_re, a_im)), (b_re, b_im)) in result.zip(a).zip(b) {
+            *re += *a_re * *b_re - *a_im * *b_im;
+            *im += *a_re * *b_im + *a_im * *b_re;
+        }
+    }
+}
+
+fn scalar_sum<S: Sample>(result: &mut [S], a: &[S], b: &[S]) {
+    for ((result, a), b) in result.iter_mut().zip(a).zip(b) {
+        *result = *a + *b;
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86 {
+    #[cfg(target_arch = "x86")]
+    use std::arch::x86::*;
+    #[cfg(target_arch = "x86_64")]
+    use std::arch::x86_64::*;
+
+    use super::{scalar_complex_multiply_accumulate, scalar_sum, InstructionSet};
+
+    // The spectra are padded to a multiple of the vector width, so the complex multiplications
+    // never leave a remainder. The sums operate on arbitrary slices, the vectorized loops return
+    // the number of processed samples and leave the rest to the scalar implementation.
+
+    /// Safety: the instruction set must be supported and the dimensions of the spectra must
+    /// match, see `scalar_complex_multiply_accumulate`.
+    pub(super) unsafe fn complex_multiply_accumulate_f32(
+        instruction_set: InstructionSet,
+        result: &mut [f32],
+        a: &[f32],
+        b: &[f32],
+        padded_bins: usize,
+    ) {
+        match instruction_set {
+            InstructionSet::Scalar => scalar_complex_multiply_accumulate(result, a, b, padded_bins),
+            InstructionSet::Sse2 => complex_multiply_accumulate_f32_sse2(result, a, b, padded_bins),
+            InstructionSet::Avx => complex_multiply_accumulate_f32_avx(result, a, b, padded_bins),
+            InstructionSet::Avx2Fma => {
+                complex_multiply_accumulate_f32_avx2_fma(result, a, b, padded_bins)
+            }
+        }
+    }
+
+    /// Safety: see `complex_multiply_accumulate_f32`.
+    pub(super) unsafe fn complex_multiply_accumulate_f64(
+        instruction_set: InstructionSet,
+        result: &mut [f64],
+        a: &[f64],
+        b: &[f64],
+        padded_bins: usize,
+    ) {
+        match instruction_set {
+            InstructionSet::Scalar => scalar_complex_multiply_accumulate(result, a, b, padded_bins),
+            InstructionSet::Sse2 => complex_multiply_accumulate_f64_sse2(result, a, b, padded_bins),
+            InstructionSet::Avx => complex_multiply_accumulate_f64_avx(result, a, b, padded_bins),
+            InstructionSet::Avx2Fma => {
+                complex_multiply_accumulate_f64_avx2_fma(result, a, b, padded_bins)
+            }
+        }
+    }
+
+    /// Safety: the instruction set must be supported and all slices must have the same length.
+    pub(super) unsafe fn sum_f32(
+        instruction_set: InstructionSet,
+        result: &mut [f32],
+        a: &[f32],
+        b: &[f32],
+    ) {
+        let done = match instruction_set {
+            InstructionSet::Scalar => 0,
+            InstructionSet::Sse2 => sum_f32_sse2(result, a, b),
+            // Additions do not benefit from FMA
+            InstructionSet::Avx | InstructionSet::Avx2Fma => sum_f32_avx(result, a, b),
+        };
+        scalar_sum(&mut result[done..], &a[done..], &b[done..]);
+    }
+
+    /// Safety: the instruction set must be supported and all slices must have the same length.
+    pub(super) unsafe fn sum_f64(
+        instruction_set: InstructionSet,
+        result: &mut [f64],
+        a: &[f64],
+        b: &[f64],
+    ) {
+        let done = match instruction_set {
+            InstructionSet::Scalar => 0,
+            InstructionSet::Sse2 => sum_f64_sse2(result, a, b),
+            InstructionSet::Avx | InstructionSet::Avx2Fma => sum_f64_avx(result, a, b),
+        };
+        scalar_sum(&mut result[done..], &a[done..], &b[done..]);
+    }
+
+    // result += sum(a[k] * b[k]), without fused multiply-add
+    macro_rules! complex_multiply_accumulate {
+        ($name:ident, $feature:literal, $sample:ty, $lanes:literal,
+         $load:ident, $store:ident, $add:ident, $sub:ident, $mul:ident) => {
+            #[target_feature(enable = $feature)]
+            unsafe fn $name(
+                result: &mut [$sample],
+                a: &[$sample],
+                b: &[$sample],
+                padded_bins: usize,
+            ) {
+                let result_re = result.as_mut_ptr();
+                let result_im = result_re.add(padded_bins);
+                for offset in (0..a.len()).step_by(2 * padded_bins) {
+                    let (a_re, b_re) = (a.as_ptr().add(offset), b.as_ptr().add(offset));
+                    let (a_im, b_im) = (a_re.add(padded_bins), b_re.add(padded_bins));
+                    for i in (0..padded_bins).step_by($lanes) {
+                        let (ar, ai) = ($load(a_re.add(i)), $load(a_im.add(i)));
+                        let (br, bi) = ($load(b_re.add(i)), $load(b_im.add(i)));
+                        let re = $sub($mul(ar, br), $mul(ai, bi));
+                        let im = $add($mul(ar, bi), $mul(ai, br));
+                        $store(result_re.add(i), $add($load(result_re.add(i)), re));
+                        $store(result_im.add(i), $add($load(result_im.add(i)), im));
+                    }
+                }
+            }
+        };
+    }
+
+    // result += sum(a[k] * b[k]), with fused multiply-add
+    macro_rules! complex_multiply_accumulate_fma {
+        ($name:ident, $sample:ty, $lanes:literal,
+         $load:ident, $store:ident, $fmadd:ident, $fnmadd:ident) => {
+            #[target_feature(enable = "avx2,fma")]
+            unsafe fn $name(
+                result: &mut [$sample],
+                a: &[$sample],
+                b: &[$sample],
+                padded_bins: usize,
+            ) {
+                let result_re = result.as_mut_ptr();
+                let result_im = result_re.add(padded_bins);
+                for offset in (0..a.len()).step_by(2 * padded_bins) {
+                    let (a_re, b_re) = (a.as_ptr().add(offset), b.as_ptr().add(offset));
+                    let (a_im, b_im) = (a_re.add(padded_bins), b_re.add(padded_bins));
+                    for i in (0..padded_bins).step_by($lanes) {
+                        let (ar, ai) = ($load(a_re.add(i)), $load(a_im.add(i)));
+                        let (br, bi) = ($load(b_re.add(i)), $load(b_im.add(i)));
+                        let re = $fnmadd(ai, bi, $fmadd(ar, br, $load(result_re.add(i))));
+                        let im = $fmadd(ai, br, $fmadd(ar, bi, $load(result_im.add(i))));
+                        $store(result_re.add(i), re);
+                        $store(result_im.add(i), im);
+                    }
+                }
+            }
+        };
+    }
+
+    // result = a + b
+    macro_rules! sum {
+        ($name:ident, $feature:literal, $sample:ty, $lanes:literal,
+         $load:ident, $store:ident, $add:ident) => {
+            #[target_feature(enable = $feature)]
+            unsafe fn $name(result: &mut [$sample], a: &[$sample], b: &[$sample]) -> usize {
+                let done = $lanes * (result.len() / $lanes);
+                for i in (0..done).step_by($lanes) {
+                    let sum = $add($load(a.as_ptr().add(i)), $load(b.as_ptr().add(i)));
+                    $store(result.as_mut_ptr().add(i), sum);
+                }
+                done
+            }
+        };
+    }
+
+    complex_multiply_accumulate!(
+        complex_multiply_accumulate_f32_sse2,
+        "sse2",
+        f32,
+        4,
+        _mm_loadu_ps,
+        _mm_storeu_ps,
+        _mm_add_ps,
+        _mm_sub_ps,
+        _mm_mul_ps
+    );
+    complex_multiply_accumulate!(
+        complex_multiply_accumulate_f32_avx,
+        "avx",
+        f32,
+        8,
+        _mm256_loadu_ps,
+        _mm256_storeu_ps,
+        _mm256_add_ps,
+        _mm256_sub_ps,
+        _mm256_mul_ps
+    );
+    complex_multiply_accumulate_fma!(
+        complex_multiply_accumulate_f32_avx2_fma,
+        f32,
+        8,
+        _mm256_loadu_ps,
+        _mm256_storeu_ps,
+        _mm256_fmadd_ps,
+        _mm256_fnmadd_ps
+    );
+    complex_multiply_accumulate!(
+        complex_multiply_accumulate_f64_sse2,
+        "sse2",
+        f64,
+        2,
+        _mm_loadu_pd,
+        _mm_storeu_pd,
+        _mm_add_pd,
+        _mm_sub_pd,
+        _mm_mul_pd
+    );
+    complex_multiply_accumulate!(
+        complex_multiply_accumulate_f64_avx,
+        "avx",
+        f64,
+        4,
+        _mm256_loadu_pd,
+        _mm256_storeu_pd,
+        _mm256_add_pd,
+        _mm256_sub_pd,
+        _mm256_mul_pd
+    );
+    complex_multiply_accumulate_fma!(
+        complex_multiply_accumulate_f64_avx2_fma,
+        f64,
+        4,
+        _mm256_loadu_pd,
+        _mm256_storeu_pd,
+        _mm256_fmadd_pd,
+        _mm256_fnmadd_pd
+    );
+
+    sum!(
+        sum_f32_sse2,
+        "sse2",
+        f32,
+        4,
+        _mm_loadu_ps,
+        _mm_storeu_ps,
+        _mm_add_ps
+    );
+    sum!(
+        sum_f32_avx,
+        "avx",
+        f32,
+        8,
+        _mm256_loadu_ps,
+        _mm256_storeu_ps,
+        _mm256_add_ps
+    );
+    sum!(
+        sum_f64_sse2,
+        "sse2",
+        f64,
+        2,
+        _mm_loadu_pd,
+        _mm_storeu_pd,
+        _mm_add_pd
+    );
+    sum!(
+        sum_f64_avx,
+        "avx",
+        f64,
+        4,
+        _mm256_loadu_pd,
+        _mm256_storeu_pd,
+        _mm256_add_pd
+    );
+}
+
+#[cfg(test)]
+fn test_signal<S: Sample>(length: usize, seed: usize) -> Vec<S> {
+    (0..length)
+        .map(|i| {
+            S::from_usize((i * 7919 + seed * 104729) % 2003).unwrap() / S::from_usize(1001).unwrap()
+                - S::one()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+fn check_complex_multiply_accumulate<S: Sample>(tolerance: S) {
+    use crate::split_complex::SplitSpectra;
+    use rustfft::num_complex::Complex;
+
+    let spectra = |count, bins, seed| {
+        let mut spectra = SplitSpectra::new(count, bins);
+        let samples = test_signal::<S>(2 * count * bins, seed);
+        for (index, samples) in samples.chunks(2 * bins).enumerate() {
+            let spectrum: Vec<_> = samples
+                .chunks(2)
+                .map(|pair| Complex::new(pair[0], pair[1]))
+                .collect();
+            spectra.store(index, &spectrum);
+        }
+        spectra
+    };
+
+    // Bin counts that are no multiple of the vector width cover the padding
+    for (count, bins) in [(0, 5), (1, 1), (1, 8), (3, 13), (2, 257), (16, 1025)] {
+        let (a, b, accumulated) = (
+            spectra(count, bins, 1),
+            spectra(count, bins, 2),
+            spectra(1, bins, 3),
+        );
+
+        let mut expected = accumulated.clone();
+        let expected = expected.get_mut(0);
+        for index in 0..count {
+            let (a, b) = (a.get(index), b.get(index));
+            for bin in 0..bins {
+                expected.re[bin] += a.re[bin] * b.re[bin] - a.im[bin] * b.im[bin];
+                expected.im[bin] += a.re[bin] * b.im[bin] + a.im[bin] * b.re[bin];
+            }
+        }
+
+        for instruction_set in InstructionSet::supported() {
+            let mut result = accumulated.clone();
+            // Safety: only supported instruction sets are tested
+            unsafe {
+                S::complex_multiply_accumulate_with(
+                    instruction_set,
+                    result.spectra_mut(0..1),
+                    a.spectra(0..count),
+                    b.spectra(0..count),
+                );
+            }
+            let result = result.get(0);
+            let result = result.re.iter().chain(result.im);
+            for (result, expected) in result.zip(expected.re.iter().chain(expected.im.iter())) {
+                assert!(
+                    (*result - *expected).abs() <= tolerance,
+                    "{instruction_set:?}: {result:?} != {expected:?}"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn check_sum<S: Sample>() {
+    for len in [0, 1, 3, 4, 7, 8, 9, 17, 1025] {
+        let (a, b) = (test_signal::<S>(len, 1), test_signal::<S>(len, 2));
+
+        let mut expected = vec![S::zero(); len];
+        // Safety: only supported instruction sets are tested
+        unsafe { S::sum_with(InstructionSet::Scalar, &mut expected, &a, &b) };
+
+        for instruction_set in InstructionSet::supported() {
+            let mut result = vec![S::one(); len];
+            unsafe { S::sum_with(instruction_set, &mut result, &a, &b) };
+            // Additions are exact across all implementations
+            assert_eq!(result, expected, "{instruction_set:?}");
+        }
+    }
+}
+
+#[test]
+fn test_complex_multiply_accumulate_instruction_sets() {
+    // FMA skips the rounding of the intermediate products, the error grows with the number of
+    // accumulated spectra
+    check_complex_multiply_accumulate::<f32>(1e-5);
+    check_complex_multiply_accumulate::<f64>(1e-13);
+}
+
+#[test]
+fn test_sum_instruction_sets() {
+    check_sum::<f32>();
+    check_sum::<f64>();
+}
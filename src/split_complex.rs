@@ -0,0 +1,206 @@
+use std::ops::Range;
+
+use rustfft::num_complex::Complex;
+
+use crate::Sample;
+
+/// A spectrum in split-complex layout, the real and imaginary parts are stored in separate
+/// slices of the same length.
+#[derive(Clone, Copy, Debug)]
+pub struct SplitComplex<'a, S> {
+    pub re: &'a [S],
+    pub im: &'a [S],
+}
+
+/// Mutable version of `SplitComplex`.
+#[derive(Debug)]
+pub struct SplitComplexMut<'a, S> {
+    pub re: &'a mut [S],
+    pub im: &'a mut [S],
+}
+
+/// A number of spectra of the same length in a single contiguous allocation.
+///
+/// The spectra are stored one after another, each of them in split-complex layout: all real
+/// parts followed by all imaginary parts. This keeps every partition of a response close in
+/// memory and lets the multiply-accumulate loop operate on whole vectors without shuffling.
+///
+/// The real and imaginary parts are padded with zeros to a multiple of `PADDING` bins, so that
+/// the vectorized loops never have to deal with a remainder.
+#[derive(Clone, Debug, Default)]
+pub struct SplitSpectra<S> {
+    count: usize,
+    bins: usize,
+    padded_bins: usize,
+    data: Vec<S>,
+}
+
+/// Consecutive spectra of a `SplitSpectra`.
+#[derive(Clone, Copy, Debug)]
+pub struct SplitSpectraRef<'a, S> {
+    count: usize,
+    padded_bins: usize,
+    data: &'a [S],
+}
+
+/// Mutable version of `SplitSpectraRef`.
+#[derive(Debug)]
+pub struct SplitSpectraMut<'a, S> {
+    count: usize,
+    padded_bins: usize,
+    data: &'a mut [S],
+}
+
+// Multiple of the widest vector of samples (8 x f32 for AVX)
+const PADDING: usize = 8;
+
+impl<S: Sample> SplitSpectra<S> {
+    /// Allocates `count` spectra of `bins` bins each, initialized to zero.
+    pub fn new(count: usize, bins: usize) -> Self {
+        let padded_bins = PADDING * ((bins + PADDING - 1) / PADDING);
+        Self {
+            count,
+            bins,
+            padded_bins,
+            data: vec![S::zero(); 2 * count * padded_bins],
+        }
+    }
+
+    /// Number of spectra.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Number of bins of each spectrum.
+    pub fn bins(&self) -> usize {
+        self.bins
+    }
+
+    pub fn get(&self, index: usize) -> SplitComplex<'_, S> {
+        let spectrum = &self.data[self.range(index..index + 1)];
+        SplitComplex {
+            re: &spectrum[..self.bins],
+            im: &spectrum[self.padded_bins..self.padded_bins + self.bins],
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> SplitComplexMut<'_, S> {
+        let range = self.range(index..index + 1);
+        let (re, im) = self.data[range].split_at_mut(self.padded_bins);
+        SplitComplexMut {
+            re: &mut re[..self.bins],
+            im: &mut im[..self.bins],
+        }
+    }
+
+    /// The spectra within `range`.
+    pub fn spectra(&self, range: Range<usize>) -> SplitSpectraRef<'_, S> {
+        SplitSpectraRef {
+            count: range.len(),
+            padded_bins: self.padded_bins,
+            data: &self.data[self.range(range)],
+        }
+    }
+
+    /// The spectra within `range`.
+    pub fn spectra_mut(&mut self, range: Range<usize>) -> SplitSpectraMut<'_, S> {
+        let data_range = self.range(range.clone());
+        SplitSpectraMut {
+            count: range.len(),
+            padded_bins: self.padded_bins,
+            data: &mut self.data[data_range],
+        }
+    }
+
+    /// Sets all bins of all spectra to zero.
+    pub fn clear(&mut self) {
+        self.data.fill(S::zero());
+    }
+
+    /// Copies all spectra from `other`, which needs to have the same dimensions.
+    pub fn copy_from(&mut self, other: &Self) {
+        assert_eq!((self.count, self.bins), (other.count, other.bins));
+        self.data.copy_from_slice(&other.data);
+    }
+
+    /// Copies the spectrum at `index` from interleaved complex numbers.
+    pub fn store(&mut self, index: usize, spectrum: &[Complex<S>]) {
+        assert_eq!(spectrum.len(), self.bins);
+        let SplitComplexMut { re, im } = self.get_mut(index);
+        for ((re, im), bin) in re.iter_mut().zip(im.iter_mut()).zip(spectrum) {
+            *re = bin.re;
+            *im = bin.im;
+        }
+    }
+
+    /// Copies the spectrum at `index` to interleaved complex numbers.
+    pub fn load(&self, index: usize, spectrum: &mut [Complex<S>]) {
+        assert_eq!(spectrum.len(), self.bins);
+        let SplitComplex { re, im } = self.get(index);
+        for ((re, im), bin) in re.iter().zip(im).zip(spectrum) {
+            *bin = Complex::new(*re, *im);
+        }
+    }
+
+    fn range(&self, range: Range<usize>) -> Range<usize> {
+        assert!(range.start <= range.end && range.end <= self.count);
+        2 * range.start * self.padded_bins..2 * range.end * self.padded_bins
+    }
+}
+
+impl<'a, S> SplitSpectraRef<'a, S> {
+    /// Number of spectra.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    // Padded real and imaginary parts of all spectra, see `SplitSpectra`
+    pub(crate) fn data(&self) -> &'a [S] {
+        self.data
+    }
+
+    pub(crate) fn padded_bins(&self) -> usize {
+        self.padded_bins
+    }
+}
+
+impl<S> SplitSpectraMut<'_, S> {
+    /// Number of spectra.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub(crate) fn data(&mut self) -> &mut [S] {
+        self.data
+    }
+
+    pub(crate) fn padded_bins(&self) -> usize {
+        self.padded_bins
+    }
+}
+
+#[test]
+fn test_split_spectra_store_and_load() {
+    let mut spectra = SplitSpectra::<f32>::new(3, 5);
+    let spectrum: Vec<_> = (0..5)
+        .map(|i| Complex::new(i as f32, -(i as f32)))
+        .collect();
+    spectra.store(1, &spectrum);
+
+    assert_eq!(spectra.get(1).re, &[0.0, 1.0, 2.0, 3.0, 4.0]);
+    assert_eq!(spectra.get(1).im, &[0.0, -1.0, -2.0, -3.0, -4.0]);
+    assert!(spectra
+        .get(0)
+        .re
+        .iter()
+        .chain(spectra.get(2).im)
+        .all(|bin| *bin == 0.0));
+
+    let mut loaded = vec![Complex::new(0.0, 0.0); 5];
+    spectra.load(1, &mut loaded);
+    assert_eq!(loaded, spectrum);
+
+    // The padding stays zero
+    assert_eq!(spectra.spectra(1..2).data()[5..8], [0.0; 3]);
+    assert_eq!(spectra.spectra(1..3).count(), 2);
+}
@@ -0,0 +1,459 @@
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::fft_convolver::FFTConvolver;
+use crate::{Convolution, ConvolutionError, Sample};
+
+/// What the audio thread does if the worker has not delivered a tail block in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnderrunPolicy {
+    /// Replace the missing tail block with silence and drop the late result once it arrives,
+    /// the audio thread never waits for the worker. Every missed block is counted as an underrun.
+    ///
+    /// The worker still convolves every input block, so only the late block is silent. Only if
+    /// the worker falls a whole queue behind, input blocks are lost as well. They are convolved
+    /// as silence to keep the worker in time, the tail then lacks their contribution for the
+    /// length of the response.
+    Silence,
+    /// Busy-wait until the worker has delivered the block. This is not real-time safe and meant
+    /// for offline rendering, where the output has to match synchronous processing exactly.
+    Wait,
+}
+
+enum Command {
+    Process(u64),
+    // Blocks that were lost while sending, they are convolved as silence
+    Skip(Range<u64>),
+    Update(usize),
+}
+
+// Number of input blocks the worker can fall behind before input is lost
+const QUEUE_BLOCKS: usize = 4;
+// Number of updates that can be queued before `update` fails
+const QUEUE_UPDATES: usize = 2;
+const IDLE_TIMEOUT: Duration = Duration::from_millis(1);
+
+struct Shared<S: Sample> {
+    convolver: Mutex<FFTConvolver<S>>,
+    shutdown: AtomicBool,
+    // One more than the sequence number of the first block after the latest reset, zero if
+    // there was none. Resets do not go through the command queue, so they can never fail.
+    reset_sequence: AtomicU64,
+}
+
+/// Runs the convolution of the 2nd-Nth tail block of a `TwoStageFFTConvolver` on a dedicated
+/// thread.
+///
+/// Completed input blocks are handed to the worker through lock-free SPSC ring buffers and the
+/// result of a block is expected one block later, which is exactly the time the synchronous
+/// implementation keeps its precalculated output around. Results are tagged with the sequence
+/// number of their input block, so late results are never played out of time.
+pub(crate) struct TailWorker<S: Sample> {
+    block_size: usize,
+    max_response_length: usize,
+    underrun_policy: UnderrunPolicy,
+    shared: Arc<Shared<S>>,
+    thread: Option<JoinHandle<()>>,
+    commands: Producer<Command>,
+    input: Producer<S>,
+    responses: Producer<S>,
+    results: Consumer<u64>,
+    output: Consumer<S>,
+    sequence: u64,
+    first_valid_sequence: u64,
+    lost: Range<u64>,
+    underruns: usize,
+}
+
+impl<S: Sample> TailWorker<S> {
+    pub(crate) fn spawn(
+        convolver: FFTConvolver<S>,
+        block_size: usize,
+        max_response_length: usize,
+        underrun_policy: UnderrunPolicy,
+    ) -> Result<Self, ConvolutionError> {
+        let shared = Arc::new(Shared {
+            convolver: Mutex::new(convolver),
+            shutdown: AtomicBool::new(false),
+            reset_sequence: AtomicU64::new(0),
+        });
+
+        let (commands, worker_commands) = RingBuffer::new(QUEUE_BLOCKS + QUEUE_UPDATES + 1);
+        let (input, worker_input) = RingBuffer::new(QUEUE_BLOCKS * block_size);
+        let (responses, worker_responses) = RingBuffer::new(QUEUE_UPDATES * max_response_length);
+        // Late results are dropped on every exchange, at most the queued input blocks and the
+        // one in progress deliver their results in the meantime
+        let (worker_results, results) = RingBuffer::new(QUEUE_BLOCKS + 1);
+        let (worker_output, output) = RingBuffer::new((QUEUE_BLOCKS + 1) * block_size);
+
+        let worker = Worker {
+            shared: shared.clone(),
+            commands: worker_commands,
+            input: worker_input,
+            responses: worker_responses,
+            results: worker_results,
+            output: worker_output,
+            input_block: vec![S::zero(); block_size],
+            output_block: vec![S::zero(); block_size],
+            response: vec![S::zero(); max_response_length],
+        };
+        let thread = thread::Builder::new()
+            .name("convolution-tail".into())
+            .spawn(move || worker.run())
+            .map_err(ConvolutionError::ThreadSpawn)?;
+
+        Ok(Self {
+            block_size,
+            max_response_length,
+            underrun_policy,
+            shared,
+            thread: Some(thread),
+            commands,
+            input,
+            responses,
+            results,
+            output,
+            sequence: 0,
+            first_valid_sequence: 0,
+            lost: 0..0,
+            underruns: 0,
+        })
+    }
+
+    /// Number of tail blocks that were replaced with silence so far.
+    pub(crate) fn underruns(&self) -> usize {
+        self.underruns
+    }
+
+    /// Hands a completed input block to the worker and fetches the result of the previous one.
+    pub(crate) fn exchange(&mut self, input: &[S], precalculated: &mut [S]) {
+        assert_eq!(input.len(), self.block_size);
+        assert_eq!(precalculated.len(), self.block_size);
+
+        let sequence = self.sequence;
+        self.sequence += 1;
+        self.send_block(sequence, input);
+
+        precalculated.fill(S::zero());
+        if sequence > self.first_valid_sequence {
+            self.receive_block(sequence - 1, precalculated);
+        } else {
+            self.drop_results_before(sequence);
+        }
+    }
+
+    /// Passes a new response to the worker, results of blocks sent before are dropped.
+    ///
+    /// Fails with `ConvolutionError::QueueFull` without changing anything if the worker has not
+    /// picked up the previous updates yet.
+    pub(crate) fn update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        assert!(response.len() <= self.max_response_length);
+
+        if !self.can_update(response.len()) {
+            self.wake_worker();
+            return Err(ConvolutionError::QueueFull);
+        }
+        write_samples(&mut self.responses, response);
+        let _ = self.commands.push(Command::Update(response.len()));
+        self.wake_worker();
+
+        self.first_valid_sequence = self.sequence;
+        Ok(())
+    }
+
+    /// Whether `update` accepts a response of `length` samples.
+    pub(crate) fn can_update(&self, length: usize) -> bool {
+        self.commands.slots() > 0 && self.responses.slots() >= length
+    }
+
+    /// Drops the signal history of the worker before it processes the next block, results of
+    /// blocks sent before are dropped.
+    pub(crate) fn reset(&mut self) {
+        self.shared
+            .reset_sequence
+            .store(self.sequence + 1, Ordering::Release);
+        self.wake_worker();
+
+        self.first_valid_sequence = self.sequence;
+    }
+
+    fn send_block(&mut self, sequence: u64, input: &[S]) {
+        loop {
+            let commands = if self.lost.is_empty() { 1 } else { 2 };
+            if self.commands.slots() >= commands && write_samples(&mut self.input, input) {
+                if !self.lost.is_empty() {
+                    let lost = std::mem::replace(&mut self.lost, 0..0);
+                    let _ = self.commands.push(Command::Skip(lost));
+                }
+                let _ = self.commands.push(Command::Process(sequence));
+                self.wake_worker();
+                return;
+            }
+
+            match self.underrun_policy {
+                UnderrunPolicy::Silence => {
+                    // The worker is a whole queue behind, the block is lost. Its result is
+                    // missing and counted as an underrun once it is due.
+                    if self.lost.is_empty() {
+                        self.lost = sequence..sequence;
+                    }
+                    self.lost.end = sequence + 1;
+                    return;
+                }
+                UnderrunPolicy::Wait => {
+                    self.drop_results_before(self.first_valid_sequence);
+                    thread::yield_now();
+                }
+            }
+        }
+    }
+
+    fn receive_block(&mut self, sequence: u64, precalculated: &mut [S]) {
+        loop {
+            self.drop_results_before(sequence);
+            if let Ok(result_sequence) = self.results.peek() {
+                if *result_sequence == sequence {
+                    let _ = self.results.pop();
+                    read_samples(&mut self.output, precalculated);
+                    return;
+                }
+                // The result of the requested block is missing, its input was lost while sending
+                self.underruns += 1;
+                return;
+            }
+
+            match self.underrun_policy {
+                UnderrunPolicy::Silence => {
+                    // The result is late, it will be dropped once it arrives
+                    self.underruns += 1;
+                    return;
+                }
+                UnderrunPolicy::Wait => thread::yield_now(),
+            }
+        }
+    }
+
+    fn drop_results_before(&mut self, sequence: u64) {
+        while let Ok(result_sequence) = self.results.peek() {
+            if *result_sequence >= sequence {
+                return;
+            }
+            let _ = self.results.pop();
+            if let Ok(chunk) = self.output.read_chunk(self.block_size) {
+                chunk.commit_all();
+            }
+        }
+    }
+
+    fn wake_worker(&self) {
+        if let Some(thread) = &self.thread {
+            thread.thread().unpark();
+        }
+    }
+}
+
+/// Cloning spawns a new worker with a copy of the tail convolver, blocks that are still in
+/// flight in the original worker are not carried over. Panics if the thread cannot be spawned.
+impl<S: Sample> Clone for TailWorker<S> {
+    fn clone(&self) -> Self {
+        let convolver = self.shared.convolver.lock().unwrap().clone();
+        let mut worker = Self::spawn(
+            convolver,
+            self.block_size,
+            self.max_response_length,
+            self.underrun_policy,
+        )
+        .unwrap_or_else(|error| panic!("{error}"));
+        worker.sequence = self.sequence;
+        worker.first_valid_sequence = self.sequence;
+        worker.underruns = self.underruns;
+        worker
+    }
+}
+
+impl<S: Sample> Drop for TailWorker<S> {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+struct Worker<S: Sample> {
+    shared: Arc<Shared<S>>,
+    commands: Consumer<Command>,
+    input: Consumer<S>,
+    responses: Consumer<S>,
+    results: Producer<u64>,
+    output: Producer<S>,
+    input_block: Vec<S>,
+    output_block: Vec<S>,
+    response: Vec<S>,
+}
+
+impl<S: Sample> Worker<S> {
+    fn run(mut self) {
+        let shared = self.shared.clone();
+        let mut applied_reset = 0;
+        while !shared.shutdown.load(Ordering::Acquire) {
+            let Ok(command) = self.commands.pop() else {
+                thread::park_timeout(IDLE_TIMEOUT);
+                continue;
+            };
+
+            let mut convolver = shared.convolver.lock().unwrap();
+            match command {
+                Command::Process(sequence) => {
+                    read_samples(&mut self.input, &mut self.input_block);
+                    self.apply_reset(&mut convolver, sequence, &mut applied_reset);
+                    convolver.process(&self.input_block, &mut self.output_block);
+                    // The audio thread drops late results on every exchange, so there is always
+                    // space unless it stopped exchanging blocks
+                    if self.results.slots() > 0 && self.output.slots() >= self.output_block.len() {
+                        write_samples(&mut self.output, &self.output_block);
+                        let _ = self.results.push(sequence);
+                    }
+                }
+                Command::Skip(sequences) => {
+                    self.input_block.fill(S::zero());
+                    for sequence in sequences {
+                        self.apply_reset(&mut convolver, sequence, &mut applied_reset);
+                        convolver.process(&self.input_block, &mut self.output_block);
+                    }
+                }
+                Command::Update(len) => {
+                    read_samples(&mut self.responses, &mut self.response[..len]);
+                    convolver.update(&self.response[..len]);
+                }
+            }
+        }
+    }
+
+    // Drops the signal history before the first block sent after the latest reset
+    fn apply_reset(&self, convolver: &mut FFTConvolver<S>, sequence: u64, applied_reset: &mut u64) {
+        let reset = self.shared.reset_sequence.load(Ordering::Acquire);
+        if reset != *applied_reset && sequence + 1 >= reset {
+            convolver.reset();
+            *applied_reset = reset;
+        }
+    }
+}
+
+fn write_samples<S: Sample>(producer: &mut Producer<S>, samples: &[S]) -> bool {
+    match producer.write_chunk_uninit(samples.len()) {
+        Ok(chunk) => {
+            chunk.fill_from_iter(samples.iter().copied());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+fn read_samples<S: Sample>(consumer: &mut Consumer<S>, samples: &mut [S]) -> bool {
+    match consumer.read_chunk(samples.len()) {
+        Ok(chunk) => {
+            let (first, second) = chunk.as_slices();
+            samples[..first.len()].copy_from_slice(first);
+            samples[first.len()..].copy_from_slice(second);
+            chunk.commit_all();
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn update_fails_if_the_queue_is_full() {
+    let response = vec![0.1f32; 1000];
+    let convolver = FFTConvolver::init(&response, 64, response.len());
+    let mut worker =
+        TailWorker::spawn(convolver, 64, response.len(), UnderrunPolicy::Wait).unwrap();
+
+    // The worker can not apply updates while the convolver is locked, at most one of them is
+    // picked up in the meantime
+    let shared = worker.shared.clone();
+    let locked = shared.convolver.lock().unwrap();
+    let results: Vec<_> = (0..QUEUE_UPDATES + 2)
+        .map(|_| worker.update(&response))
+        .collect();
+    assert!(results[..QUEUE_UPDATES].iter().all(Result::is_ok));
+    assert!(matches!(
+        results.last(),
+        Some(Err(ConvolutionError::QueueFull))
+    ));
+    drop(locked);
+
+    while worker.update(&response).is_err() {
+        thread::sleep(Duration::from_micros(100));
+    }
+}
+
+#[test]
+fn silence_on_underrun() {
+    let block_size = 64;
+    let response: Vec<f32> = (0..1000).map(|i| 1.0 / (1 + i) as f32).collect();
+    let input: Vec<Vec<f32>> = (0..12)
+        .map(|block| {
+            (0..block_size)
+                .map(|i| ((block * block_size + i) as f32 * 0.1).sin())
+                .collect()
+        })
+        .collect();
+    let convolver = FFTConvolver::init(&response, block_size, response.len());
+    let mut reference = convolver.clone();
+    let mut worker = TailWorker::spawn(
+        convolver,
+        block_size,
+        response.len(),
+        UnderrunPolicy::Silence,
+    )
+    .unwrap();
+    let mut precalculated = vec![0.0; block_size];
+    let wait_for_results = |worker: &TailWorker<f32>, count: usize| {
+        while worker.results.slots() < count {
+            thread::sleep(Duration::from_micros(100));
+        }
+    };
+
+    // The worker can not convolve anything while the convolver is locked, the queue takes
+    // four blocks and the following two are lost
+    let shared = worker.shared.clone();
+    let locked = shared.convolver.lock().unwrap();
+    for block in &input[..QUEUE_BLOCKS + 2] {
+        worker.exchange(block, &mut precalculated);
+        assert!(precalculated.iter().all(|sample| *sample == 0.0));
+    }
+    assert_eq!(worker.underruns(), QUEUE_BLOCKS + 1);
+    drop(locked);
+
+    // The lost blocks are convolved as silence, so the worker stays in time
+    let mut expected = vec![vec![0.0; block_size]; input.len()];
+    for (index, (block, expected)) in input.iter().zip(&mut expected).enumerate() {
+        if (QUEUE_BLOCKS..QUEUE_BLOCKS + 2).contains(&index) {
+            reference.process(&vec![0.0; block_size], expected);
+        } else {
+            reference.process(block, expected);
+        }
+    }
+
+    wait_for_results(&worker, QUEUE_BLOCKS);
+    worker.exchange(&input[QUEUE_BLOCKS + 2], &mut precalculated);
+    assert!(precalculated.iter().all(|sample| *sample == 0.0));
+    assert_eq!(worker.underruns(), QUEUE_BLOCKS + 2);
+
+    for index in QUEUE_BLOCKS + 3..input.len() {
+        wait_for_results(&worker, 1);
+        worker.exchange(&input[index], &mut precalculated);
+        for (sample, expected) in precalculated.iter().zip(&expected[index - 1]) {
+            assert!((sample - expected).abs() < 1e-6);
+        }
+    }
+    assert_eq!(worker.underruns(), QUEUE_BLOCKS + 2);
+}
@@ -1,121 +1,1850 @@
-#[cfg(test)]
-mod tests {
-    use crate::crossfade_convolver::CrossfadeConvolver;
-    use crate::fft_convolver::FFTConvolver;
-    use crate::{Convolution, Sample};
-
-    fn generate_sinusoid(
-        length: usize,
-        frequency: f32,
-        sample_rate: f32,
-        gain: f32,
-    ) -> Vec<Sample> {
-        let mut signal = vec![0.0; length];
-        for i in 0..length {
-            signal[i] =
-                gain * (2.0 * std::f32::consts::PI * frequency * i as Sample / sample_rate).sin();
-        }
-        signal
-    }
-
-    #[test]
-    fn fft_convolver_update_is_reset() {
-        let block_size = 512;
-        let response_a = generate_sinusoid(block_size, 1000.0, 48000.0, 1.0);
-        let response_b = generate_sinusoid(block_size, 2000.0, 48000.0, 0.7);
-        let mut convolver_a = FFTConvolver::init(&response_a, block_size, response_a.len());
-        let mut convolver_b = FFTConvolver::init(&response_b, block_size, response_b.len());
-        let mut convolver_update = FFTConvolver::init(&response_a, block_size, response_a.len());
-        let mut output_a = vec![0.0; block_size];
-        let mut output_b = vec![0.0; block_size];
-        let mut output_update = vec![0.0; block_size];
-
-        let num_input_blocks = 16;
-        let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
-
-        let update_index = 8;
-
-        for i in 0..num_input_blocks {
-            if i == update_index {
-                convolver_update.update(&response_b);
-            }
+use crate::crossfade_convolver::{
+    CosineMixer, CrossfadeConvolver, CrossfadeConvolverBuilder, Crossfader, LinearMixer, Mixer,
+    PendingUpdatePolicy, RaisedCosineMixer, SquareRootMixer, Target, UpdateEvent, UpdateStatus,
+};
+use crate::fft_convolver::{FFTConvolver, TwoStageFFTConvolver, UpdateMode};
+use crate::frequency_domain_crossfade_convolver::FrequencyDomainCrossfadeConvolver;
+use crate::multi_stage_convolver::MultiStageFFTConvolver;
+use crate::partitioned_response::PartitionedResponse;
+use crate::response_channel::response_channel;
+use crate::scheduled_convolver::ScheduledConvolver;
+use crate::tail_worker::UnderrunPolicy;
+use crate::{BlockSizePolicy, Convolution, ConvolutionError, Sample};
+use std::sync::Arc;
+
+fn generate_sinusoid<S: Sample>(
+    length: usize,
+    frequency: f64,
+    sample_rate: f64,
+    gain: f64,
+) -> Vec<S> {
+    (0..length)
+        .map(|i| {
+            sample(gain * (2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate).sin())
+        })
+        .collect()
+}
 
-            convolver_update.process(
-                &input[i * block_size..(i + 1) * block_size],
-                &mut output_update,
-            );
+fn sample<S: Sample>(value: f64) -> S {
+    S::from_f64(value).unwrap()
+}
 
-            let check_equal = |lhs: &[Sample], rhs: &[Sample]| {
-                for j in 0..block_size {
-                    assert!((lhs[j] - rhs[j]).abs() < 1e-6);
+macro_rules! test_precisions {
+    ($($name:ident),* $(,)?) => {
+        mod f32 {
+            $(
+                #[test]
+                fn $name() {
+                    super::$name::<f32>();
                 }
-            };
+            )*
+        }
 
-            if i < update_index {
-                convolver_a.process(&input[i * block_size..(i + 1) * block_size], &mut output_a);
-                check_equal(&output_a, &output_update);
-            } else {
-                convolver_b.process(&input[i * block_size..(i + 1) * block_size], &mut output_b);
-                check_equal(&output_b, &output_update);
+        mod f64 {
+            $(
+                #[test]
+                fn $name() {
+                    super::$name::<f64>();
+                }
+            )*
+        }
+    };
+}
+
+fn fft_convolver_update_is_reset<S: Sample>() {
+    let block_size = 512;
+    let response_a: Vec<S> = generate_sinusoid(block_size, 1000.0, 48000.0, 1.0);
+    let response_b: Vec<S> = generate_sinusoid(block_size, 2000.0, 48000.0, 0.7);
+    let mut convolver_a = FFTConvolver::init(&response_a, block_size, response_a.len());
+    let mut convolver_b = FFTConvolver::init(&response_b, block_size, response_b.len());
+    let mut convolver_update = FFTConvolver::init(&response_a, block_size, response_a.len());
+    let mut output_a = vec![S::zero(); block_size];
+    let mut output_b = vec![S::zero(); block_size];
+    let mut output_update = vec![S::zero(); block_size];
+
+    let num_input_blocks = 16;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+
+    let update_index = 8;
+
+    for i in 0..num_input_blocks {
+        if i == update_index {
+            convolver_update.update(&response_b);
+        }
+
+        convolver_update.process(
+            &input[i * block_size..(i + 1) * block_size],
+            &mut output_update,
+        );
+
+        let check_equal = |lhs: &[S], rhs: &[S]| {
+            for j in 0..block_size {
+                assert!((lhs[j] - rhs[j]).abs() < sample(1e-6));
             }
+        };
+
+        if i < update_index {
+            convolver_a.process(&input[i * block_size..(i + 1) * block_size], &mut output_a);
+            check_equal(&output_a, &output_update);
+        } else {
+            convolver_b.process(&input[i * block_size..(i + 1) * block_size], &mut output_b);
+            check_equal(&output_b, &output_update);
         }
     }
+}
 
-    #[test]
-    fn test_crossfade_convolver() {
-        let block_size = 512;
-        let response_a = generate_sinusoid(block_size, 1000.0, 48000.0, 1.0);
-        let response_b = generate_sinusoid(block_size, 2000.0, 48000.0, 0.7);
-        let mut convolver_a = FFTConvolver::init(&response_a, block_size, response_a.len());
-        let mut convolver_b = FFTConvolver::init(&response_b, block_size, response_b.len());
-        let mut crossfade_convolver =
-            CrossfadeConvolver::new(convolver_a.clone(), block_size, block_size, block_size);
-        let mut output_a = vec![0.0; block_size];
-        let mut output_b = vec![0.0; block_size];
-        let mut output_crossfade_convolver = vec![0.0; block_size];
+fn test_crossfade_convolver<S: Sample>() {
+    let block_size = 512;
+    let response_a: Vec<S> = generate_sinusoid(block_size, 1000.0, 48000.0, 1.0);
+    let response_b: Vec<S> = generate_sinusoid(block_size, 2000.0, 48000.0, 0.7);
+    let mut convolver_a = FFTConvolver::init(&response_a, block_size, response_a.len());
+    let mut convolver_b = FFTConvolver::init(&response_b, block_size, response_b.len());
+    let mut crossfade_convolver =
+        CrossfadeConvolver::new(convolver_a.clone(), block_size, block_size, block_size);
+    let mut output_a = vec![S::zero(); block_size];
+    let mut output_b = vec![S::zero(); block_size];
+    let mut output_crossfade_convolver = vec![S::zero(); block_size];
 
-        let num_input_blocks = 16;
-        let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+    let num_input_blocks = 16;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
 
-        let update_index = 8;
+    let update_index = 8;
+
+    for i in 0..num_input_blocks {
+        if i == update_index {
+            crossfade_convolver.update(&response_b);
+        }
 
-        for i in 0..num_input_blocks {
-            if i == update_index {
-                crossfade_convolver.update(&response_b);
+        crossfade_convolver.process(
+            &input[i * block_size..(i + 1) * block_size],
+            &mut output_crossfade_convolver,
+        );
+
+        let check_equal = |lhs: &[S], rhs: &[S]| {
+            for j in 0..block_size {
+                assert!((lhs[j] - rhs[j]).abs() < sample(1e-6));
             }
+        };
 
-            crossfade_convolver.process(
-                &input[i * block_size..(i + 1) * block_size],
-                &mut output_crossfade_convolver,
-            );
+        convolver_a.process(&input[i * block_size..(i + 1) * block_size], &mut output_a);
+        if i >= update_index {
+            convolver_b.process(&input[i * block_size..(i + 1) * block_size], &mut output_b);
+        }
 
-            let check_equal = |lhs: &[Sample], rhs: &[Sample]| {
-                for j in 0..block_size {
-                    assert!((lhs[j] - rhs[j]).abs() < 1e-6);
-                }
+        if i <= update_index {
+            check_equal(&output_a, &output_crossfade_convolver);
+        } else {
+            if i == update_index + 1 {
+                // crossover sample
+                let crossover_index = block_size / 2 - 1;
+                assert!(
+                    (output_crossfade_convolver[crossover_index]
+                        - (output_a[crossover_index] * sample(0.5)
+                            + output_b[crossover_index] * sample(0.5)))
+                    .abs()
+                        < sample(1e-6)
+                );
+            } else {
+                check_equal(&output_b, &output_crossfade_convolver);
+            }
+        }
+    }
+}
+
+// Deterministic pseudo random samples in [-gain, gain)
+fn generate_noise<S: Sample>(length: usize, seed: u32, gain: f64) -> Vec<S> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            sample(gain * (state as f64 / u32::MAX as f64 * 2.0 - 1.0))
+        })
+        .collect()
+}
+
+// The responses span many partitions, so the output after the fade depends on the whole input
+// history of the convolver that was faded in. The hold period covers the output the convolver
+// drops on an update.
+fn check_crossfade_long_response<S: Sample, C: Convolution<S>>(hold_samples: usize) {
+    let block_size = 64;
+    let response_a: Vec<S> = generate_noise(2000, 0x1234_5678, 0.1);
+    let response_b: Vec<S> = generate_noise(2000, 0x8765_4321, 0.1);
+    let input: Vec<S> = generate_noise(120 * block_size, 0x0bad_cafe, 1.0);
+    let reference_a = convolve_reference(&input, &response_a);
+    let reference_b = convolve_reference(&input, &response_b);
+
+    let mut convolver: CrossfadeConvolver<C, S> =
+        CrossfadeConvolverBuilder::new(block_size, response_a.len())
+            .crossfade_samples(256)
+            .hold_samples(hold_samples)
+            .build(&response_a);
+    let update_index = 40;
+    let mut faded = false;
+    let mut output = vec![S::zero(); block_size];
+    for (i, block) in input.chunks(block_size).enumerate() {
+        if i == update_index {
+            convolver.update(&response_b);
+        }
+        convolver.process(block, &mut output);
+
+        let expected = if i < update_index {
+            &reference_a[i * block_size..(i + 1) * block_size]
+        } else if faded {
+            &reference_b[i * block_size..(i + 1) * block_size]
+        } else {
+            faded = !convolver.is_crossfading();
+            continue;
+        };
+        for (output, expected) in output.iter().zip(expected) {
+            assert!((*output - *expected).abs() < sample(1e-4));
+        }
+    }
+    assert!(faded);
+}
+
+fn crossfade_convolver_long_response<S: Sample>() {
+    // One block, two tail blocks and two blocks of the largest used stage of 1024 samples
+    check_crossfade_long_response::<S, FFTConvolver<S>>(64);
+    check_crossfade_long_response::<S, TwoStageFFTConvolver<S>>(2048);
+    check_crossfade_long_response::<S, MultiStageFFTConvolver<S>>(2048);
+}
+
+// Feeds part of the input, the output matches a convolution of the whole input once the output
+// dropped for the fed input has passed
+fn check_feed_keeps_history<S: Sample, C: Convolution<S>>(settle_samples: usize) {
+    let block_size = 64;
+    let response: Vec<S> = generate_noise(2000, 0x1234_5678, 0.1);
+    let input: Vec<S> = generate_noise(120 * block_size, 0x0bad_cafe, 1.0);
+    let reference = convolve_reference(&input, &response);
+
+    let mut convolver = C::init(&response, block_size, response.len());
+    let (feed_begin, feed_end) = (40 * block_size, 80 * block_size);
+    let mut output = vec![S::zero(); input.len()];
+    convolver.process(&input[..feed_begin], &mut output[..feed_begin]);
+    convolver.try_feed(&input[feed_begin..feed_end]).unwrap();
+    for (input, output) in input[feed_end..]
+        .chunks(block_size)
+        .zip(output[feed_end..].chunks_mut(block_size))
+    {
+        convolver.process(input, output);
+    }
+
+    let compared = (0..feed_begin).chain(feed_end + settle_samples..input.len());
+    for i in compared {
+        assert!((output[i] - reference[i]).abs() < sample(1e-4));
+    }
+}
+
+fn feed_keeps_history<S: Sample>() {
+    check_feed_keeps_history::<S, FFTConvolver<S>>(64);
+    check_feed_keeps_history::<S, TwoStageFFTConvolver<S>>(2048);
+    check_feed_keeps_history::<S, MultiStageFFTConvolver<S>>(2048);
+}
+
+fn check_crossfade_mixer<S: Sample, M: Mixer>(mixer: M) {
+    let block_size = 256;
+    let response_a: Vec<S> = generate_sinusoid(block_size, 1000.0, 48000.0, 1.0);
+    let response_b: Vec<S> = generate_sinusoid(block_size, 2000.0, 48000.0, 0.7);
+    let mut crossfade_convolver = CrossfadeConvolver::with_mixer(
+        FFTConvolver::init(&response_a, block_size, response_a.len()),
+        block_size,
+        block_size,
+        block_size,
+        mixer.clone(),
+    );
+    let mut output = vec![S::zero(); block_size];
+
+    let input = generate_sinusoid(8 * block_size, 1300.0, 48000.0, 1.0);
+    let reference_a = convolve_reference(&input, &response_a);
+    let reference_b = convolve_reference(&input, &response_b);
+    for (i, block) in input.chunks(block_size).enumerate() {
+        // Each update holds for one block
+        match i {
+            2 => crossfade_convolver.update(&response_b),
+            5 => crossfade_convolver.update(&response_a),
+            _ => {}
+        }
+        crossfade_convolver.process(block, &mut output);
+        let output_a = &reference_a[i * block_size..(i + 1) * block_size];
+        let output_b = &reference_b[i * block_size..(i + 1) * block_size];
+
+        for j in 0..block_size {
+            let value = sample::<S>((j + 1) as f64 / block_size as f64);
+            let expected = match i {
+                3 => mixer.mix(output_a[j], output_b[j], value),
+                6 => mixer.mix(output_a[j], output_b[j], S::one() - value),
+                4 | 5 => output_b[j],
+                _ => output_a[j],
             };
+            assert!((output[j] - expected).abs() < sample(1e-4));
+        }
+    }
+}
 
-            convolver_a.process(&input[i * block_size..(i + 1) * block_size], &mut output_a);
-            if i >= update_index {
-                convolver_b.process(&input[i * block_size..(i + 1) * block_size], &mut output_b);
+fn crossfade_convolver_mixers<S: Sample>() {
+    check_crossfade_mixer::<S, _>(LinearMixer);
+    check_crossfade_mixer::<S, _>(SquareRootMixer);
+    check_crossfade_mixer::<S, _>(CosineMixer);
+    check_crossfade_mixer::<S, _>(RaisedCosineMixer);
+
+    // Equal power fades keep the level of decorrelated signals
+    let (a, b) = (sample::<S>(1.0), sample::<S>(1.0));
+    let value = sample::<S>(0.5);
+    assert!((LinearMixer.mix(a, b, value) - sample(1.0)).abs() < sample(1e-6));
+    assert!((SquareRootMixer.mix(a, b, value) - S::SQRT_2()).abs() < sample(1e-6));
+    assert!((CosineMixer.mix(a, b, value) - S::SQRT_2()).abs() < sample(1e-6));
+}
+
+fn two_stage_fft_convolver_matches_fft_convolver<S: Sample>() {
+    let block_size = 256;
+    let response: Vec<S> = generate_sinusoid(5000, 700.0, 48000.0, 0.1);
+    let mut convolver = FFTConvolver::init(&response, block_size, response.len());
+    let mut two_stage_convolver = TwoStageFFTConvolver::init(&response, block_size, response.len());
+    let mut output = vec![S::zero(); block_size];
+    let mut output_two_stage = vec![S::zero(); block_size];
+
+    let num_input_blocks = 48;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+
+    for i in 0..num_input_blocks {
+        let input_block = &input[i * block_size..(i + 1) * block_size];
+        convolver.process(input_block, &mut output);
+        two_stage_convolver.process(input_block, &mut output_two_stage);
+
+        for j in 0..block_size {
+            assert!((output[j] - output_two_stage[j]).abs() < sample(1e-4));
+        }
+    }
+}
+
+fn two_stage_fft_convolver_update_keeps_history<S: Sample>() {
+    let block_size = 256;
+    let max_response_length = 5000;
+    let response_a: Vec<S> = generate_sinusoid(max_response_length, 1000.0, 48000.0, 0.1);
+    let response_b: Vec<S> = generate_sinusoid(3000, 2000.0, 48000.0, 0.07);
+    let mut convolver_a = TwoStageFFTConvolver::init(&response_a, block_size, max_response_length);
+    let mut convolver_b = TwoStageFFTConvolver::init(&response_b, block_size, max_response_length);
+    let mut convolver_update =
+        TwoStageFFTConvolver::init(&response_a, block_size, max_response_length);
+    let mut output_a = vec![S::zero(); block_size];
+    let mut output_b = vec![S::zero(); block_size];
+    let mut output_update = vec![S::zero(); block_size];
+
+    let num_input_blocks = 48;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+
+    // update in the middle of a tail block
+    let update_index = 21;
+    // The tail block following the update still lacks the dropped overlap of the tail convolver,
+    // from the one after it on the output matches the new response applied to the whole input
+    let settled_index = 32;
+
+    for i in 0..num_input_blocks {
+        if i == update_index {
+            convolver_update.update(&response_b);
+        }
+
+        let input_block = &input[i * block_size..(i + 1) * block_size];
+        convolver_update.process(input_block, &mut output_update);
+
+        let check_equal = |lhs: &[S], rhs: &[S]| {
+            for j in 0..block_size {
+                assert!((lhs[j] - rhs[j]).abs() < sample(1e-4));
             }
+        };
 
-            if i <= update_index {
-                check_equal(&output_a, &output_crossfade_convolver);
-            } else {
-                if i == update_index + 1 {
-                    // crossover sample
-                    let crossover_index = block_size / 2 - 1;
-                    assert!(
-                        (output_crossfade_convolver[crossover_index]
-                            - (output_a[crossover_index] * 0.5 + output_b[crossover_index] * 0.5))
-                            .abs()
-                            < 1e-6
-                    );
-                } else {
-                    check_equal(&output_b, &output_crossfade_convolver);
-                }
+        convolver_b.process(input_block, &mut output_b);
+        if i < update_index {
+            convolver_a.process(input_block, &mut output_a);
+            check_equal(&output_a, &output_update);
+        } else if i >= settled_index {
+            check_equal(&output_b, &output_update);
+        }
+    }
+}
+
+fn two_stage_fft_convolver_block_sizes<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+    let num_input_samples = 16384;
+    let input = generate_sinusoid(num_input_samples, 1300.0, 48000.0, 1.0);
+
+    for (block_size, head_block_size, tail_block_size) in [
+        (32, 32, 1024),
+        (64, 64, 256),
+        (256, 256, 2048),
+        (96, 32, 512),
+    ] {
+        let mut convolver = FFTConvolver::init(&response, block_size, response.len());
+        let mut two_stage_convolver = TwoStageFFTConvolver::with_block_sizes(
+            &response,
+            head_block_size,
+            tail_block_size,
+            response.len(),
+        );
+        assert_eq!(two_stage_convolver.head_block_size(), head_block_size);
+        assert_eq!(two_stage_convolver.tail_block_size(), tail_block_size);
+
+        let mut output = vec![S::zero(); block_size];
+        let mut output_two_stage = vec![S::zero(); block_size];
+        for input_block in input.chunks_exact(block_size) {
+            convolver.process(input_block, &mut output);
+            two_stage_convolver.process(input_block, &mut output_two_stage);
+
+            for j in 0..block_size {
+                assert!((output[j] - output_two_stage[j]).abs() < sample(1e-4));
+            }
+        }
+    }
+}
+
+fn two_stage_fft_convolver_default_block_sizes<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+
+    let convolver = TwoStageFFTConvolver::init(&response, 32, response.len());
+    assert_eq!(convolver.head_block_size(), 32);
+    assert_eq!(convolver.tail_block_size(), 1024);
+
+    let convolver = TwoStageFFTConvolver::init(&response, 480, response.len());
+    assert_eq!(convolver.head_block_size(), 512);
+    assert_eq!(convolver.tail_block_size(), 4096);
+}
+
+#[test]
+#[should_panic(expected = "tail_block_size must be a multiple of head_block_size")]
+fn two_stage_fft_convolver_tail_smaller_than_head() {
+    let response: Vec<f32> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+    TwoStageFFTConvolver::with_block_sizes(&response, 256, 128, response.len());
+}
+
+#[test]
+#[should_panic(expected = "tail_block_size must be a multiple of head_block_size")]
+fn two_stage_fft_convolver_tail_not_a_multiple() {
+    // The tail has to complete its blocks at head block boundaries
+    let response: Vec<f32> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+    TwoStageFFTConvolver::with_block_sizes(&response, 96, 1024, response.len());
+}
+
+fn two_stage_fft_convolver_non_power_of_two<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(9600, 1300.0, 48000.0, 1.0);
+    let expected = convolve_reference(&input, &response);
+
+    for (head_block_size, tail_block_size) in [(96, 960), (480, 1440), (100, 300)] {
+        let convolver = TwoStageFFTConvolver::with_block_sizes(
+            &response,
+            head_block_size,
+            tail_block_size,
+            response.len(),
+        );
+        check_matches_reference(convolver, head_block_size, &input, &expected);
+    }
+
+    let convolver = TwoStageFFTConvolver::with_block_size_policy(
+        &response,
+        480,
+        response.len(),
+        BlockSizePolicy::Exact,
+    );
+    assert_eq!(convolver.head_block_size(), 480);
+    assert_eq!(convolver.tail_block_size(), 3840);
+    check_matches_reference(convolver, 480, &input, &expected);
+}
+
+#[test]
+#[should_panic(expected = "each block size must be a multiple of the previous one")]
+fn multi_stage_fft_convolver_not_a_multiple() {
+    let response: Vec<f32> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+    MultiStageFFTConvolver::with_block_sizes(&response, &[96, 400, 1600], response.len());
+}
+
+fn multi_stage_fft_convolver_matches_fft_convolver<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(20000, 700.0, 48000.0, 0.05);
+    let num_input_samples = 40000;
+    let input = generate_sinusoid(num_input_samples, 1300.0, 48000.0, 1.0);
+
+    for (block_size, block_sizes) in [
+        (64, vec![64, 256, 1024, 8192]),
+        (32, vec![32, 128, 512, 2048, 8192]),
+        (100, vec![128, 1024, 4096]),
+        (256, vec![256]),
+    ] {
+        let mut convolver = FFTConvolver::init(&response, block_size, response.len());
+        let mut multi_stage_convolver =
+            MultiStageFFTConvolver::with_block_sizes(&response, &block_sizes, response.len());
+        assert_eq!(multi_stage_convolver.block_sizes(), block_sizes);
+
+        let mut output = vec![S::zero(); block_size];
+        let mut output_multi_stage = vec![S::zero(); block_size];
+        for input_block in input.chunks_exact(block_size) {
+            convolver.process(input_block, &mut output);
+            multi_stage_convolver.process(input_block, &mut output_multi_stage);
+
+            for j in 0..block_size {
+                assert!((output[j] - output_multi_stage[j]).abs() < sample(1e-3));
+            }
+        }
+    }
+}
+
+fn multi_stage_fft_convolver_default_block_sizes<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(100000, 700.0, 48000.0, 0.1);
+    let convolver = MultiStageFFTConvolver::init(&response, 32, response.len());
+    assert_eq!(convolver.block_sizes(), [32, 128, 512, 2048, 8192]);
+
+    let convolver = MultiStageFFTConvolver::init(&response[..3000], 64, 3000);
+    assert_eq!(convolver.block_sizes(), [64, 256, 1024, 4096]);
+}
+
+fn multi_stage_fft_convolver_update_keeps_history<S: Sample>() {
+    let block_size = 64;
+    let max_response_length = 12000;
+    let response_a: Vec<S> = generate_sinusoid(max_response_length, 1000.0, 48000.0, 0.05);
+    let response_b: Vec<S> = generate_sinusoid(10000, 2000.0, 48000.0, 0.05);
+    let mut convolver_a =
+        MultiStageFFTConvolver::init(&response_a, block_size, max_response_length);
+    let mut convolver_b =
+        MultiStageFFTConvolver::init(&response_b, block_size, max_response_length);
+    let mut convolver_update =
+        MultiStageFFTConvolver::init(&response_a, block_size, max_response_length);
+    let mut output_a = vec![S::zero(); block_size];
+    let mut output_b = vec![S::zero(); block_size];
+    let mut output_update = vec![S::zero(); block_size];
+
+    let num_input_blocks = 448;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+
+    // Update shortly before the end of a block of the largest stage (8192 samples), which
+    // settles at the start of its second block after the update. Dropping the input history
+    // would take the length of the new response instead.
+    let update_index = 250;
+    let settled_index = 384;
+
+    for i in 0..num_input_blocks {
+        if i == update_index {
+            convolver_update.update(&response_b);
+        }
+
+        let input_block = &input[i * block_size..(i + 1) * block_size];
+        convolver_update.process(input_block, &mut output_update);
+
+        let check_equal = |lhs: &[S], rhs: &[S]| {
+            for j in 0..block_size {
+                assert!((lhs[j] - rhs[j]).abs() < sample(1e-4));
+            }
+        };
+
+        convolver_b.process(input_block, &mut output_b);
+        if i < update_index {
+            convolver_a.process(input_block, &mut output_a);
+            check_equal(&output_a, &output_update);
+        } else if i >= settled_index {
+            check_equal(&output_b, &output_update);
+        }
+    }
+}
+
+fn two_stage_fft_convolver_background_tail<S: Sample>() {
+    let block_size = 128;
+    let max_response_length = 6000;
+    let response_a: Vec<S> = generate_sinusoid(max_response_length, 1000.0, 48000.0, 0.1);
+    let response_b: Vec<S> = generate_sinusoid(4000, 2000.0, 48000.0, 0.07);
+    let mut convolver = TwoStageFFTConvolver::init(&response_a, block_size, max_response_length);
+    let mut background_convolver =
+        TwoStageFFTConvolver::init(&response_a, block_size, max_response_length)
+            .with_background_tail(UnderrunPolicy::Wait);
+    let mut output = vec![S::zero(); block_size];
+    let mut output_background = vec![S::zero(); block_size];
+
+    let num_input_blocks = 200;
+    let input = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+
+    let update_index = 77;
+
+    for i in 0..num_input_blocks {
+        if i == update_index {
+            convolver.update(&response_b);
+            background_convolver.update(&response_b);
+        }
+
+        let input_block = &input[i * block_size..(i + 1) * block_size];
+        convolver.process(input_block, &mut output);
+        background_convolver.process(input_block, &mut output_background);
+
+        for j in 0..block_size {
+            assert!((output[j] - output_background[j]).abs() < sample(1e-6));
+        }
+    }
+    assert_eq!(background_convolver.tail_underruns(), 0);
+}
+
+#[test]
+fn fallible_construction_and_update() {
+    let response: Vec<f32> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+
+    assert!(matches!(
+        FFTConvolver::try_init(&response, 128, 1000),
+        Err(ConvolutionError::ResponseTooLong {
+            length: 3000,
+            max_length: 1000
+        })
+    ));
+    assert!(matches!(
+        FFTConvolver::try_init(&response, 0, response.len()),
+        Err(ConvolutionError::ZeroBlockSize)
+    ));
+    assert!(matches!(
+        TwoStageFFTConvolver::try_init(&response, 0, response.len()),
+        Err(ConvolutionError::ZeroBlockSize)
+    ));
+    assert!(matches!(
+        TwoStageFFTConvolver::try_with_block_sizes(&response, 96, 1024, response.len()),
+        Err(ConvolutionError::InvalidBlockSize(_))
+    ));
+    assert!(matches!(
+        MultiStageFFTConvolver::try_with_block_sizes(&response, &[256, 128], response.len()),
+        Err(ConvolutionError::InvalidBlockSize(_))
+    ));
+
+    let mut convolver = FFTConvolver::try_init(&response[..1000], 128, 1000).unwrap();
+    assert!(matches!(
+        convolver.try_update(&response),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+    assert!(convolver.try_update(&response[..500]).is_ok());
+
+    let mut convolver = TwoStageFFTConvolver::try_init(&response[..1000], 128, 1000).unwrap();
+    assert!(matches!(
+        convolver.try_update(&response),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+
+    let mut convolver = MultiStageFFTConvolver::try_init(&response[..1000], 128, 1000).unwrap();
+    assert!(matches!(
+        convolver.try_update(&response),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+
+    let mut convolver =
+        CrossfadeConvolver::<FFTConvolver, f32>::try_init(&response[..1000], 128, 1000).unwrap();
+    assert!(matches!(
+        convolver.try_update(&response),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+}
+
+#[test]
+#[should_panic(expected = "response length 3000 exceeds the maximum response length 1000")]
+fn update_panics_on_long_response() {
+    let response: Vec<f32> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+    let mut convolver = FFTConvolver::init(&response[..1000], 128, 1000);
+    convolver.update(&response);
+}
+
+#[test]
+fn process_buffer_size_mismatch() {
+    let response: Vec<f32> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+    let input = vec![1.0; 128];
+    let mut output = vec![1.0; 64];
+
+    let mut convolver = FFTConvolver::init(&response, 64, response.len());
+    assert!(matches!(
+        convolver.try_process(&input, &mut output),
+        Err(ConvolutionError::BufferSizeMismatch {
+            expected: 64,
+            actual: 128
+        })
+    ));
+
+    // Buffers larger than the maximum buffer size are processed in chunks
+    let mut convolver = CrossfadeConvolver::<FFTConvolver, f32>::init(&response, 32, 3000);
+    assert!(convolver.try_process(&input, &mut vec![1.0; 128]).is_ok());
+    assert!(matches!(
+        convolver.try_process(&input, &mut output),
+        Err(ConvolutionError::BufferSizeMismatch {
+            expected: 64,
+            actual: 128
+        })
+    ));
+
+    // the non-fallible version silences the output
+    convolver.process(&input, &mut output);
+    assert!(output.iter().all(|sample| *sample == 0.0));
+}
+
+fn convolver_introspection<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+
+    let mut convolver = FFTConvolver::init(&response, 100, 5000);
+    assert_eq!(convolver.latency(), 0);
+    assert_eq!(convolver.block_size(), 128);
+    assert_eq!(convolver.max_response_length(), 5000);
+    assert_eq!(convolver.response_length(), 3000);
+    assert_eq!(convolver.tail_length(), 2999);
+    convolver.update(&response[..1000]);
+    assert_eq!(convolver.response_length(), 1000);
+
+    let mut convolver = TwoStageFFTConvolver::init(&response, 64, 5000);
+    assert_eq!(convolver.latency(), 0);
+    assert_eq!(convolver.block_size(), 64);
+    assert_eq!(convolver.max_response_length(), 5000);
+    assert_eq!(convolver.response_length(), 3000);
+    convolver.update(&response[..1000]);
+    assert_eq!(convolver.response_length(), 1000);
+
+    let mut convolver = MultiStageFFTConvolver::init(&response, 32, 5000);
+    assert_eq!(convolver.latency(), 0);
+    assert_eq!(convolver.block_size(), 32);
+    assert_eq!(convolver.max_response_length(), 5000);
+    assert_eq!(convolver.response_length(), 3000);
+    convolver.update(&response[..1000]);
+    assert_eq!(convolver.response_length(), 1000);
+
+    let block_size = 128;
+    let mut convolver = CrossfadeConvolver::new(
+        FFTConvolver::init(&response, block_size, 5000),
+        5000,
+        block_size,
+        block_size,
+    );
+    assert_eq!(convolver.latency(), 0);
+    assert_eq!(convolver.block_size(), block_size);
+    assert_eq!(convolver.max_response_length(), 5000);
+    assert_eq!(convolver.response_length(), 3000);
+    convolver.update(&response[..1000]);
+    assert_eq!(convolver.response_length(), 1000);
+    // the previous response is still fading out
+    assert_eq!(convolver.tail_length(), 2999);
+
+    let input = vec![S::zero(); block_size];
+    let mut output = vec![S::zero(); block_size];
+    // A hold period and a crossfade of one block each
+    for _ in 0..2 {
+        convolver.process(&input, &mut output);
+    }
+    assert_eq!(convolver.tail_length(), 999);
+}
+
+// Processes some signal, resets the convolver and expects the same output as from a copy that
+// never processed anything
+fn check_reset_matches_fresh<S: Sample, C: Convolution<S>>(mut convolver: C, block_size: usize) {
+    let mut fresh = convolver.clone();
+    let num_input_blocks = 24;
+    let input: Vec<S> = generate_sinusoid(num_input_blocks * block_size, 1300.0, 48000.0, 1.0);
+    let mut output = vec![S::zero(); block_size];
+    let mut output_fresh = vec![S::zero(); block_size];
+
+    for block in input.chunks(block_size).take(num_input_blocks / 2) {
+        convolver.process(block, &mut output);
+    }
+    convolver.reset();
+
+    for block in input.chunks(block_size) {
+        convolver.process(block, &mut output);
+        fresh.process(block, &mut output_fresh);
+        for (lhs, rhs) in output.iter().zip(&output_fresh) {
+            assert!((*lhs - *rhs).abs() < sample(1e-6));
+        }
+    }
+}
+
+fn convolver_reset_clears_history<S: Sample>() {
+    let block_size = 256;
+    let response: Vec<S> = generate_sinusoid(6000, 700.0, 48000.0, 0.1);
+
+    check_reset_matches_fresh(
+        FFTConvolver::init(&response, block_size, response.len()),
+        block_size,
+    );
+    check_reset_matches_fresh(
+        TwoStageFFTConvolver::with_block_sizes(&response, 128, 1024, response.len()),
+        block_size,
+    );
+    check_reset_matches_fresh(
+        TwoStageFFTConvolver::with_block_sizes(&response, 128, 1024, response.len())
+            .with_background_tail(UnderrunPolicy::Wait),
+        block_size,
+    );
+    check_reset_matches_fresh(
+        MultiStageFFTConvolver::with_block_sizes(&response, &[64, 256, 1024], response.len()),
+        block_size,
+    );
+}
+
+fn crossfade_convolver_reset_completes_crossfade<S: Sample>() {
+    let block_size = 256;
+    let response_a: Vec<S> = generate_sinusoid(1000, 700.0, 48000.0, 0.1);
+    let response_b: Vec<S> = generate_sinusoid(1000, 1100.0, 48000.0, 0.1);
+    let mut convolver = CrossfadeConvolver::new(
+        FFTConvolver::init(&response_a, block_size, response_a.len()),
+        response_a.len(),
+        block_size,
+        4 * block_size,
+    );
+    let mut fresh = CrossfadeConvolver::new(
+        FFTConvolver::init(&response_b, block_size, response_b.len()),
+        response_b.len(),
+        block_size,
+        4 * block_size,
+    );
+
+    let input: Vec<S> = generate_sinusoid(8 * block_size, 1300.0, 48000.0, 1.0);
+    let mut output = vec![S::zero(); block_size];
+    let mut output_fresh = vec![S::zero(); block_size];
+
+    convolver.update(&response_b);
+    convolver.process(&input[..block_size], &mut output);
+    assert!(convolver.is_crossfading());
+    convolver.reset();
+    assert!(!convolver.is_crossfading());
+
+    for block in input.chunks(block_size) {
+        convolver.process(block, &mut output);
+        fresh.process(block, &mut output_fresh);
+        for (lhs, rhs) in output.iter().zip(&output_fresh) {
+            assert!((*lhs - *rhs).abs() < sample(1e-6));
+        }
+    }
+}
+
+// Direct convolution as a reference for the partitioned implementations
+fn convolve_reference<S: Sample>(input: &[S], response: &[S]) -> Vec<S> {
+    (0..input.len())
+        .map(|n| {
+            let mut sum = S::zero();
+            for (k, coefficient) in response.iter().enumerate().take(n + 1) {
+                sum += *coefficient * input[n - k];
             }
+            sum
+        })
+        .collect()
+}
+
+fn check_matches_reference<S: Sample, C: Convolution<S>>(
+    mut convolver: C,
+    block_size: usize,
+    input: &[S],
+    expected: &[S],
+) {
+    let mut output = vec![S::zero(); input.len()];
+    for (input, output) in input.chunks(block_size).zip(output.chunks_mut(block_size)) {
+        convolver.process(input, output);
+    }
+    for (result, expected) in output.iter().zip(expected) {
+        assert!((*result - *expected).abs() < sample(1e-4));
+    }
+}
+
+fn exact_block_sizes<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(10000, 700.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(12000, 1300.0, 48000.0, 1.0);
+    let expected = convolve_reference(&input, &response);
+
+    for block_size in [96, 480, 1000] {
+        let convolver = FFTConvolver::with_block_size_policy(
+            &response,
+            block_size,
+            response.len(),
+            BlockSizePolicy::Exact,
+        );
+        assert_eq!(convolver.block_size(), block_size);
+        check_matches_reference(convolver, block_size, &input, &expected);
+
+        let convolver = TwoStageFFTConvolver::with_block_size_policy(
+            &response,
+            block_size,
+            response.len(),
+            BlockSizePolicy::Exact,
+        );
+        assert_eq!(convolver.head_block_size(), block_size);
+        assert_eq!(convolver.tail_block_size() % block_size, 0);
+        check_matches_reference(convolver, block_size, &input, &expected);
+
+        let convolver = MultiStageFFTConvolver::with_block_size_policy(
+            &response,
+            block_size,
+            response.len(),
+            BlockSizePolicy::Exact,
+        );
+        assert_eq!(convolver.block_sizes()[0], block_size);
+        check_matches_reference(convolver, block_size, &input, &expected);
+
+        let convolver: FFTConvolver<S> = FFTConvolver::init(&response, block_size, response.len());
+        assert_eq!(convolver.block_size(), block_size.next_power_of_two());
+        check_matches_reference(convolver, block_size, &input, &expected);
+    }
+}
+
+fn shared_partitioned_response<S: Sample>() {
+    let block_size = 128;
+    let response: Vec<S> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+    let other_response: Vec<S> = generate_sinusoid(2000, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(4000, 1300.0, 48000.0, 1.0);
+    let expected = convolve_reference(&input, &response);
+    let other_expected = convolve_reference(&input, &other_response);
+
+    let partitioned = Arc::new(PartitionedResponse::new(&response, block_size));
+    assert_eq!(partitioned.len(), response.len());
+    assert_eq!(partitioned.partition_count(), 24);
+
+    let first = FFTConvolver::with_response(partitioned.clone(), response.len());
+    let second = FFTConvolver::with_response(partitioned.clone(), response.len());
+    assert_eq!(Arc::strong_count(&partitioned), 3);
+    assert_eq!(first.response_length(), response.len());
+
+    // Clones copy the response instead of sharing it
+    let clone = first.clone();
+    assert_eq!(Arc::strong_count(&partitioned), 3);
+    check_matches_reference(clone, block_size, &input, &expected);
+
+    // Updating a shared response leaves the other convolvers alone
+    let mut updated = second;
+    updated.update(&other_response);
+    assert_eq!(Arc::strong_count(&partitioned), 2);
+    assert!(!Arc::ptr_eq(updated.response(), &partitioned));
+    check_matches_reference(updated, block_size, &input, &other_expected);
+    check_matches_reference(first, block_size, &input, &expected);
+
+    // Swapping hands out the previous response
+    let mut convolver = FFTConvolver::init(&other_response, block_size, response.len());
+    let mut swapped = partitioned.clone();
+    convolver.set_response(&mut swapped).unwrap();
+    assert_eq!(swapped.len(), other_response.len());
+    assert!(Arc::ptr_eq(convolver.response(), &partitioned));
+    assert_eq!(convolver.response_length(), response.len());
+    check_matches_reference(convolver, block_size, &input, &expected);
+
+    // Rejected responses are handed back
+    let mut convolver = FFTConvolver::init(&other_response, block_size, other_response.len());
+    let mut rejected = partitioned.clone();
+    assert!(matches!(
+        convolver.set_response(&mut rejected),
+        Err(ConvolutionError::ResponseTooLong {
+            length: 3000,
+            max_length: 2000
+        })
+    ));
+    assert!(Arc::ptr_eq(&rejected, &partitioned));
+    assert!(matches!(
+        convolver.set_response(&mut Arc::new(PartitionedResponse::new(
+            &other_response,
+            256
+        ))),
+        Err(ConvolutionError::InvalidBlockSize(_))
+    ));
+    assert!(matches!(
+        FFTConvolver::try_with_response(partitioned.clone(), 1000),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+
+    // Later updates are transformed into the response, it has to have space for all of them
+    let mut convolver = FFTConvolver::init(&other_response, block_size, response.len());
+    let short = Arc::new(PartitionedResponse::new(&other_response, block_size));
+    assert!(matches!(
+        convolver.set_response(&mut short.clone()),
+        Err(ConvolutionError::InsufficientCapacity {
+            capacity: 2048,
+            max_length: 3000
+        })
+    ));
+    assert!(matches!(
+        FFTConvolver::try_with_response(short, response.len()),
+        Err(ConvolutionError::InsufficientCapacity { .. })
+    ));
+    let mut reserved = Arc::new(PartitionedResponse::with_capacity(
+        &other_response,
+        block_size,
+        response.len(),
+    ));
+    assert_eq!(reserved.capacity(), 3072);
+    convolver.set_response(&mut reserved).unwrap();
+    drop(reserved);
+    convolver.update(&response);
+    check_matches_reference(convolver, block_size, &input, &expected);
+}
+
+fn response_channel_hand_off<S: Sample>() {
+    let block_size = 128;
+    let response: Vec<S> = generate_sinusoid(3000, 700.0, 48000.0, 0.1);
+    let other_response: Vec<S> = generate_sinusoid(2000, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(4000, 1300.0, 48000.0, 1.0);
+    let expected = convolve_reference(&input, &other_response);
+
+    let mut convolver = FFTConvolver::init(&response, block_size, response.len());
+    let initial = convolver.response().clone();
+    let (mut sender, mut receiver) = response_channel(&convolver, 2);
+
+    assert!(!receiver.receive(&mut convolver));
+
+    // Only the most recent response is swapped in, the other one is retired right away
+    sender.send_response(&response);
+    let shared = Arc::new(PartitionedResponse::with_capacity(
+        &other_response,
+        block_size,
+        response.len(),
+    ));
+    sender.try_send(shared.clone()).unwrap();
+    assert_eq!(sender.pending(), 2);
+    assert!(matches!(
+        sender.try_send(shared.clone()),
+        Err(ConvolutionError::QueueFull)
+    ));
+    assert!(matches!(
+        sender.try_send(Arc::new(PartitionedResponse::new(&other_response, 256))),
+        Err(ConvolutionError::InvalidBlockSize(_))
+    ));
+    assert!(matches!(
+        sender.try_send(Arc::new(PartitionedResponse::new(
+            &other_response,
+            block_size
+        ))),
+        Err(ConvolutionError::InsufficientCapacity {
+            capacity: 2048,
+            max_length: 3000
+        })
+    ));
+    assert!(matches!(
+        sender.try_send_response(&input),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+
+    assert!(receiver.receive(&mut convolver));
+    assert_eq!(sender.pending(), 0);
+    assert!(Arc::ptr_eq(convolver.response(), &shared));
+    assert_eq!(Arc::strong_count(&initial), 2);
+
+    // The previous responses are released by the sender
+    assert_eq!(sender.collect(), 2);
+    assert_eq!(Arc::strong_count(&initial), 1);
+
+    // A response rejected by the convolver is retired as well, the current one stays in place.
+    // The sender reserved space for a shorter maximum response length than this convolver has.
+    let mut longer = FFTConvolver::init(&other_response, block_size, 4000);
+    let current = longer.response().clone();
+    sender.send_response(&response);
+    assert!(!receiver.receive(&mut longer));
+    assert_eq!(sender.pending(), 0);
+    assert!(Arc::ptr_eq(longer.response(), &current));
+    assert_eq!(sender.collect(), 1);
+
+    check_matches_reference(convolver, block_size, &input, &expected);
+}
+
+fn response_channel_across_threads<S: Sample>() {
+    let block_size = 64;
+    let responses: Vec<Vec<S>> = (1..=8)
+        .map(|i| generate_sinusoid(1000 + 100 * i, 100.0 * i as f64, 48000.0, 0.1))
+        .collect();
+    let input: Vec<S> = generate_sinusoid(block_size, 1300.0, 48000.0, 1.0);
+
+    let mut convolver = FFTConvolver::init(&responses[0], block_size, 2000);
+    let (mut sender, mut receiver) = response_channel(&convolver, 2);
+
+    let control = std::thread::spawn(move || {
+        for response in &responses {
+            while sender.try_send_response(response).is_err() {
+                std::thread::sleep(std::time::Duration::from_micros(100));
+            }
+        }
+        while sender.pending() > 0 {
+            sender.collect();
+            std::thread::sleep(std::time::Duration::from_micros(100));
+        }
+        sender.collect();
+        sender
+    });
+
+    let mut output = vec![S::zero(); block_size];
+    while !control.is_finished() {
+        receiver.receive(&mut convolver);
+        convolver.process(&input, &mut output);
+    }
+    control.join().unwrap();
+
+    assert_eq!(convolver.response_length(), 1800);
+    assert_eq!(Arc::strong_count(convolver.response()), 1);
+}
+
+fn fft_convolver_amortised_update<S: Sample>() {
+    let block_size = 128;
+    let response: Vec<S> = generate_sinusoid(2000, 700.0, 48000.0, 0.1);
+    let new_response: Vec<S> = generate_sinusoid(3000, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(20 * block_size, 1300.0, 48000.0, 1.0);
+
+    let mut convolver = FFTConvolver::init(&response, block_size, new_response.len());
+    let mut previous = convolver.clone();
+    let mut next = FFTConvolver::init(&new_response, block_size, new_response.len());
+    convolver.set_update_budget(4);
+
+    let mut output = vec![S::zero(); block_size];
+    let mut expected = vec![S::zero(); block_size];
+    let mut expected_next = vec![S::zero(); block_size];
+    let (before, after) = input.split_at(4 * block_size);
+    for block in before.chunks(block_size) {
+        convolver.process(block, &mut output);
+        previous.process(block, &mut expected);
+        next.process(block, &mut expected_next);
+    }
+
+    // 24 partitions at 4 per block, the previous response stays in use for 5 blocks. The block
+    // in which the new response is swapped in lacks the overlap, the input history is kept.
+    convolver.begin_update(&new_response);
+    for (i, block) in after.chunks(block_size).enumerate() {
+        assert_eq!(convolver.update_pending(), i < 6);
+        convolver.process(block, &mut output);
+        previous.process(block, &mut expected);
+        next.process(block, &mut expected_next);
+        let expected = match i {
+            0..=4 => &expected,
+            5 => continue,
+            _ => &expected_next,
+        };
+        for (output, expected) in output.iter().zip(expected) {
+            assert!((*output - *expected).abs() < sample(1e-5));
+        }
+    }
+    assert_eq!(convolver.response_length(), new_response.len());
+
+    // Without a budget the update is immediate
+    convolver.set_update_budget(0);
+    convolver.begin_update(&response);
+    assert!(!convolver.update_pending());
+    assert_eq!(convolver.response_length(), response.len());
+
+    assert!(matches!(
+        convolver.try_begin_update(&[S::zero(); 4000]),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+
+    // A shared response is never written to
+    convolver.try_set_update_budget(4).unwrap();
+    let mut shared = Arc::new(PartitionedResponse::with_capacity(
+        &new_response,
+        block_size,
+        new_response.len(),
+    ));
+    convolver.set_response(&mut shared).unwrap();
+    let shared = convolver.response().clone();
+    assert!(matches!(
+        convolver.try_begin_update(&response),
+        Err(ConvolutionError::SharedResponse)
+    ));
+    drop(shared);
+    convolver.begin_update(&response);
+    for block in input.chunks(block_size).take(6) {
+        convolver.process(block, &mut output);
+    }
+    assert!(!convolver.update_pending());
+    assert_eq!(convolver.response_length(), response.len());
+}
+
+fn fft_convolver_update_keeps_history<S: Sample>() {
+    let block_size = 128;
+    let chunk_size = 100;
+    let responses: [Vec<S>; 3] = [
+        generate_noise(2000, 0x1234_5678, 0.1),
+        generate_noise(1000, 0x8765_4321, 0.1),
+        generate_noise(1800, 0x0bad_cafe, 0.1),
+    ];
+    let input: Vec<S> = generate_noise(6000, 0xdead_beef, 1.0);
+    let expected: Vec<Vec<S>> = responses
+        .iter()
+        .map(|response| convolve_reference(&input, response))
+        .collect();
+
+    // The responses are switched within blocks, after 700 and 1500 samples
+    let switches = [7, 15];
+    let mut convolver = FFTConvolver::init(&responses[0], block_size, 2000);
+    let mut output = vec![S::zero(); input.len()];
+    for (i, (input, output)) in input
+        .chunks(chunk_size)
+        .zip(output.chunks_mut(chunk_size))
+        .enumerate()
+    {
+        if i == switches[0] {
+            convolver.update(&responses[1]);
         }
+        if i == switches[1] {
+            let response = PartitionedResponse::with_capacity(&responses[2], block_size, 2000);
+            convolver.set_response(&mut Arc::new(response)).unwrap();
+        }
+        convolver.process(input, output);
+    }
+
+    // Only the rest of the block in which the response is switched lacks the overlap, from the
+    // next block on the new response applies to the whole input history
+    for (n, result) in output.iter().enumerate() {
+        let response = match n {
+            0..=699 => 0,
+            768..=1499 => 1,
+            1536.. => 2,
+            _ => continue,
+        };
+        assert!((*result - expected[response][n]).abs() < sample(1e-4));
     }
 }
+
+// Switches to a short response while the input goes silent and back to the long response
+// once the silence is longer than it. The input from before the silence must not come back,
+// although stages beyond the short response had an empty response in between.
+fn check_short_to_long_update<S: Sample, C: Convolution<S>>(
+    mut convolver: C,
+    block_size: usize,
+    short_length: usize,
+) {
+    let max_response_length = convolver.max_response_length();
+    let response: Vec<S> = generate_noise(max_response_length, 0x1234_5678, 0.1);
+    let input: Vec<S> = generate_noise(2 * max_response_length, 0xdead_beef, 1.0);
+    let silence = vec![S::zero(); 2 * max_response_length];
+
+    convolver.update(&response);
+    process_blocks(&mut convolver, &input, block_size, |_, _| {});
+    convolver.update(&response[..short_length]);
+    process_blocks(&mut convolver, &silence, block_size, |_, _| {});
+    convolver.update(&response);
+    let output = process_blocks(&mut convolver, &silence, block_size, |_, _| {});
+
+    assert!(output.iter().all(|sample| *sample == S::zero()));
+}
+
+fn short_to_long_update_is_silent<S: Sample>() {
+    let block_size = 128;
+    let max_response_length = 6000;
+    let response = vec![S::zero(); max_response_length];
+
+    for short_length in [0, 100] {
+        check_short_to_long_update(
+            FFTConvolver::init(&response, block_size, max_response_length),
+            block_size,
+            short_length,
+        );
+        check_short_to_long_update(
+            TwoStageFFTConvolver::init(&response, block_size, max_response_length),
+            block_size,
+            short_length,
+        );
+        check_short_to_long_update(
+            TwoStageFFTConvolver::init(&response, block_size, max_response_length)
+                .with_background_tail(UnderrunPolicy::Wait),
+            block_size,
+            short_length,
+        );
+    }
+}
+
+fn multi_stage_fft_convolver_short_to_long_update<S: Sample>() {
+    let block_size = 128;
+    let max_response_length = 6000;
+    let response = vec![S::zero(); max_response_length];
+
+    // The short responses end before the first tail stage
+    for short_length in [0, 100] {
+        check_short_to_long_update(
+            MultiStageFFTConvolver::init(&response, block_size, max_response_length),
+            block_size,
+            short_length,
+        );
+        check_short_to_long_update(
+            MultiStageFFTConvolver::with_block_sizes(
+                &response,
+                &[block_size, 4 * block_size, 16 * block_size],
+                max_response_length,
+            ),
+            block_size,
+            short_length,
+        );
+    }
+}
+
+fn fft_convolver_update_preserves_history<S: Sample>() {
+    let block_size = 128;
+    let chunk_size = 100;
+    let responses: [Vec<S>; 3] = [
+        generate_sinusoid(3000, 700.0, 48000.0, 0.1),
+        generate_sinusoid(1000, 300.0, 48000.0, 0.1),
+        generate_sinusoid(2500, 1100.0, 48000.0, 0.1),
+    ];
+    let input: Vec<S> = generate_sinusoid(6000, 1300.0, 48000.0, 1.0);
+    let expected: Vec<Vec<S>> = responses
+        .iter()
+        .map(|response| convolve_reference(&input, response))
+        .collect();
+
+    // The responses are switched within blocks, after 700 and 1500 samples
+    let switches = [7, 15];
+    let mut convolver = FFTConvolver::init(&responses[0], block_size, 3000);
+    convolver.set_update_mode(UpdateMode::PreserveHistory);
+    let mut output = vec![S::zero(); input.len()];
+    for (i, (input, output)) in input
+        .chunks(chunk_size)
+        .zip(output.chunks_mut(chunk_size))
+        .enumerate()
+    {
+        if i == switches[0] {
+            convolver.update(&responses[1]);
+        }
+        if i == switches[1] {
+            let response = PartitionedResponse::with_capacity(&responses[2], block_size, 3000);
+            convolver.set_response(&mut Arc::new(response)).unwrap();
+        }
+        convolver.process(input, output);
+    }
+
+    // The output is the convolution with the response active at each sample, including the
+    // input that was processed before the switch
+    for (n, result) in output.iter().enumerate() {
+        let response = switches
+            .iter()
+            .filter(|switch| n >= *switch * chunk_size)
+            .count();
+        assert!((*result - expected[response][n]).abs() < sample(1e-4));
+    }
+
+    // Amortised updates complete without a discontinuity as well
+    let mut convolver = FFTConvolver::init(&responses[0], block_size, 3000);
+    convolver.set_update_mode(UpdateMode::PreserveHistory);
+    convolver.set_update_budget(8);
+    let mut output = vec![S::zero(); input.len()];
+    let mut switch = None;
+    for (i, (input, output)) in input
+        .chunks(chunk_size)
+        .zip(output.chunks_mut(chunk_size))
+        .enumerate()
+    {
+        if i == switches[0] {
+            convolver.begin_update(&responses[2]);
+        }
+        let pending = convolver.update_pending();
+        convolver.process(input, output);
+        if pending && !convolver.update_pending() {
+            switch = Some(i * chunk_size);
+        }
+    }
+    let switch = switch.unwrap();
+    for (n, result) in output.iter().enumerate() {
+        let response = if n < switch { 0 } else { 2 };
+        assert!((*result - expected[response][n]).abs() < sample(1e-4));
+    }
+}
+
+fn frequency_domain_crossfade_convolver<S: Sample>() {
+    let block_size = 128;
+    let chunk_size = 100;
+    let crossfade_samples = 1000;
+    let responses: [Vec<S>; 3] = [
+        generate_sinusoid(3000, 700.0, 48000.0, 0.1),
+        generate_sinusoid(2000, 300.0, 48000.0, 0.1),
+        generate_sinusoid(2500, 1100.0, 48000.0, 0.1),
+    ];
+    let input: Vec<S> = generate_sinusoid(6000, 1300.0, 48000.0, 1.0);
+    let references: Vec<Vec<S>> = responses
+        .iter()
+        .map(|response| convolve_reference(&input, response))
+        .collect();
+
+    let mut convolver = FrequencyDomainCrossfadeConvolver::new(
+        &responses[0],
+        block_size,
+        3000,
+        crossfade_samples,
+        BlockSizePolicy::RoundUp,
+    );
+    let mut output = vec![S::zero(); input.len()];
+    for (i, (input, output)) in input
+        .chunks(chunk_size)
+        .zip(output.chunks_mut(chunk_size))
+        .enumerate()
+    {
+        match i {
+            7 => convolver.update(&responses[1]),
+            // Stored until the running crossfade is complete
+            10 => convolver.update(&responses[2]),
+            _ => {}
+        }
+        assert_eq!(
+            convolver.is_crossfading(),
+            (7..17).contains(&i) || (18..27).contains(&i)
+        );
+        convolver.process(input, output);
+    }
+    assert_eq!(convolver.response_length(), responses[2].len());
+
+    // The fades start within blocks, the second one as soon as the first one is complete
+    let mut crossfader = Crossfader::new(RaisedCosineMixer, crossfade_samples, 0);
+    let mut a = &references[0];
+    let b = &references[1];
+    for (n, result) in output.iter().enumerate() {
+        if n == 700 {
+            crossfader.fade_into(Target::B);
+        }
+        if n == 1700 {
+            a = &references[2];
+            crossfader.fade_into(Target::A);
+        }
+        let expected = crossfader.mix(a[n], b[n]);
+        assert!((*result - expected).abs() < sample(1e-4));
+    }
+}
+
+// Counts the processed and the fed blocks of all its clones
+#[derive(Clone)]
+struct CountingConvolver<S: Sample> {
+    convolver: FFTConvolver<S>,
+    processed: std::rc::Rc<std::cell::Cell<usize>>,
+    fed: std::rc::Rc<std::cell::Cell<usize>>,
+}
+
+impl<S: Sample> Convolution<S> for CountingConvolver<S> {
+    fn try_init(
+        response: &[S],
+        max_block_size: usize,
+        max_response_length: usize,
+    ) -> Result<Self, ConvolutionError> {
+        Ok(Self {
+            convolver: FFTConvolver::try_init(response, max_block_size, max_response_length)?,
+            processed: Default::default(),
+            fed: Default::default(),
+        })
+    }
+
+    fn try_update(&mut self, response: &[S]) -> Result<(), ConvolutionError> {
+        self.convolver.try_update(response)
+    }
+
+    fn try_process(&mut self, input: &[S], output: &mut [S]) -> Result<(), ConvolutionError> {
+        self.processed.set(self.processed.get() + 1);
+        self.convolver.try_process(input, output)
+    }
+
+    fn try_feed(&mut self, input: &[S]) -> Result<(), ConvolutionError> {
+        self.fed.set(self.fed.get() + 1);
+        self.convolver.try_feed(input)
+    }
+
+    fn reset(&mut self) {
+        self.convolver.reset();
+    }
+
+    fn latency(&self) -> usize {
+        self.convolver.latency()
+    }
+
+    fn block_size(&self) -> usize {
+        self.convolver.block_size()
+    }
+
+    fn max_response_length(&self) -> usize {
+        self.convolver.max_response_length()
+    }
+
+    fn response_length(&self) -> usize {
+        self.convolver.response_length()
+    }
+}
+
+fn crossfade_convolver_suspends_idle_convolver<S: Sample>() {
+    let block_size = 64;
+    let fading_samples = 2 * block_size;
+    let response_a: Vec<S> = generate_sinusoid(1000, 700.0, 48000.0, 0.1);
+    let response_b: Vec<S> = generate_sinusoid(700, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(80 * block_size, 1300.0, 48000.0, 1.0);
+    let reference_a = convolve_reference(&input, &response_a);
+    let reference_b = convolve_reference(&input, &response_b);
+
+    let counting = CountingConvolver::init(&response_a, block_size, 1000);
+    let processed = counting.processed.clone();
+    let fed = counting.fed.clone();
+    let mut convolver =
+        CrossfadeConvolver::with_mixer(counting, 1000, block_size, fading_samples, LinearMixer);
+
+    // The idle convolver reapplies its history to the new response instead of dropping the
+    // overlap
+    let mut preserving = FFTConvolver::init(&response_a, block_size, 1000);
+    preserving.set_update_mode(UpdateMode::PreserveHistory);
+    let mut preserving =
+        CrossfadeConvolver::with_mixer(preserving, 1000, block_size, fading_samples, LinearMixer);
+
+    // Both convolvers match a direct convolution with their response once the hold period of
+    // one block has passed, the idle convolver was fed all of the input
+    let mut crossfader = Crossfader::<LinearMixer, S>::new(LinearMixer, fading_samples, block_size);
+    let mut output = vec![S::zero(); block_size];
+    let mut expected = vec![S::zero(); block_size];
+    for (i, block) in input.chunks(block_size).enumerate() {
+        let (response, target) = match i {
+            10 => (Some(&response_b), Target::B),
+            40 => (Some(&response_a), Target::A),
+            _ => (None, Target::A),
+        };
+        if let Some(response) = response {
+            convolver.update(response);
+            preserving.update(response);
+            crossfader.fade_into(target);
+        }
+
+        // Both convolvers are only processed during the hold period and the crossfade, otherwise
+        // the idle one is fed
+        let fading = crossfader.is_fading();
+        let range = i * block_size..(i + 1) * block_size;
+        crossfader.mix_block(
+            &reference_a[range.clone()],
+            &reference_b[range],
+            &mut expected,
+        );
+
+        let (processed_before, fed_before) = (processed.get(), fed.get());
+        convolver.process(block, &mut output);
+        assert_eq!(
+            processed.get() - processed_before,
+            if fading { 2 } else { 1 }
+        );
+        assert_eq!(fed.get() - fed_before, if fading { 0 } else { 1 });
+        for (output, expected) in output.iter().zip(&expected) {
+            assert!((*output - *expected).abs() < sample(1e-4));
+        }
+
+        preserving.process(block, &mut output);
+        for (output, expected) in output.iter().zip(&expected) {
+            assert!((*output - *expected).abs() < sample(1e-4));
+        }
+    }
+}
+
+// Deterministic pseudo random chunk sizes from 1 to `max_size`
+fn random_chunk_sizes(count: usize, max_size: usize) -> Vec<usize> {
+    let mut state = 0x1234_5678u32;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            1 + state as usize % max_size
+        })
+        .collect()
+}
+
+fn crossfade_convolver_arbitrary_process_lengths<S: Sample>() {
+    let block_size = 64;
+    let response_a: Vec<S> = generate_sinusoid(2000, 700.0, 48000.0, 0.1);
+    let response_b: Vec<S> = generate_sinusoid(1500, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(8000, 1300.0, 48000.0, 1.0);
+
+    let new_convolver = || {
+        let mut convolver = CrossfadeConvolver::new(
+            FFTConvolver::init(&response_a, block_size, 2000),
+            2000,
+            block_size,
+            1000,
+        );
+        // The crossfade runs within the large call
+        convolver.update(&response_b);
+        convolver
+    };
+
+    let mut expected = vec![S::zero(); input.len()];
+    new_convolver().process(&input, &mut expected);
+
+    let mut convolver = new_convolver();
+    let mut output = vec![S::zero(); input.len()];
+    let mut position = 0;
+    for chunk_size in random_chunk_sizes(1000, 5 * block_size) {
+        let end = input.len().min(position + chunk_size);
+        convolver.process(&input[position..end], &mut output[position..end]);
+        position = end;
+        if position == input.len() {
+            break;
+        }
+    }
+    assert_eq!(position, input.len());
+
+    for (output, expected) in output.iter().zip(&expected) {
+        assert!((*output - *expected).abs() < sample(1e-5));
+    }
+    // The crossfade to the new response is complete
+    let reference_b = convolve_reference(&input, &response_b);
+    assert!((output[7000] - reference_b[7000]).abs() < sample(1e-4));
+}
+
+fn crossfade_convolver_builder<S: Sample>() {
+    let block_size = 16;
+    let short_response: Vec<S> = generate_sinusoid(500, 700.0, 48000.0, 0.1);
+    let long_response: Vec<S> = generate_sinusoid(2500, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(1000, 1300.0, 48000.0, 1.0);
+    let mut output = vec![S::zero(); block_size];
+
+    // The trait path keeps the maximum response length
+    let mut convolver = CrossfadeConvolver::<FFTConvolver<S>, S>::init(&short_response, 64, 3000);
+    assert_eq!(convolver.max_response_length(), 3000);
+    convolver.update(&long_response);
+    assert_eq!(convolver.response_length(), long_response.len());
+
+    let mut convolver: CrossfadeConvolver<FFTConvolver<S>, S, LinearMixer> =
+        CrossfadeConvolverBuilder::new(block_size, 3000)
+            .crossfade_seconds(0.01, 48000.0)
+            .hold_samples(64)
+            .mixer(LinearMixer)
+            .build(&short_response);
+    convolver.update(&long_response);
+    for (i, block) in input.chunks(block_size).enumerate() {
+        assert_eq!(convolver.is_crossfading(), i * block_size < 64 + 480);
+        convolver.process(block, &mut output);
+    }
+
+    let builder = || CrossfadeConvolverBuilder::new(block_size, 3000);
+    let try_build = |builder: CrossfadeConvolverBuilder| {
+        builder
+            .try_build::<FFTConvolver<S>, S>(&long_response)
+            .map(|_| ())
+    };
+    assert!(try_build(builder()).is_ok());
+    assert!(matches!(
+        try_build(builder().crossfade_samples(0)),
+        Err(ConvolutionError::InvalidParameter(_))
+    ));
+    assert!(matches!(
+        try_build(builder().crossfade_seconds(-1.0, 48000.0)),
+        Err(ConvolutionError::InvalidParameter(_))
+    ));
+    assert!(matches!(
+        try_build(builder().hold_seconds(0.1, 0.0)),
+        Err(ConvolutionError::InvalidParameter(_))
+    ));
+    assert!(matches!(
+        try_build(CrossfadeConvolverBuilder::new(0, 3000)),
+        Err(ConvolutionError::ZeroBlockSize)
+    ));
+    assert!(matches!(
+        try_build(CrossfadeConvolverBuilder::new(block_size, 1000)),
+        Err(ConvolutionError::ResponseTooLong {
+            length: 2500,
+            max_length: 1000
+        })
+    ));
+}
+
+fn crossfade_convolver_pending_updates<S: Sample>() {
+    let block_size = 16;
+    let responses: Vec<Vec<S>> = (1..=4)
+        .map(|i| generate_sinusoid(100 * i, 700.0, 48000.0, 0.1))
+        .collect();
+    let input: Vec<S> = generate_sinusoid(1024, 1300.0, 48000.0, 1.0);
+    let mut output = vec![S::zero(); block_size];
+
+    let builder = || {
+        CrossfadeConvolverBuilder::new(block_size, 400)
+            .crossfade_samples(64)
+            .hold_samples(0)
+    };
+    let events = |convolver: &mut CrossfadeConvolver<FFTConvolver<S>, S>| {
+        std::iter::from_fn(|| convolver.pop_update_event()).collect::<Vec<_>>()
+    };
+    let run = |convolver: &mut CrossfadeConvolver<FFTConvolver<S>, S>| {
+        let mut output = vec![S::zero(); block_size];
+        for block in input.chunks(block_size) {
+            convolver.process(block, &mut output);
+        }
+    };
+
+    // The latest update replaces the pending one
+    let mut convolver: CrossfadeConvolver<FFTConvolver<S>, S> = builder().build(&responses[0]);
+    let first = convolver.queue_update(&responses[1]);
+    let dropped = convolver.queue_update(&responses[2]);
+    let latest = convolver.queue_update(&responses[3]);
+    assert_eq!(convolver.update_status(first), UpdateStatus::FadingIn);
+    assert_eq!(convolver.update_status(dropped), UpdateStatus::Inactive);
+    assert_eq!(convolver.update_status(latest), UpdateStatus::Pending);
+    run(&mut convolver);
+    assert_eq!(
+        events(&mut convolver),
+        [
+            UpdateEvent::Started(first),
+            UpdateEvent::Dropped(dropped),
+            UpdateEvent::Finished(first),
+            UpdateEvent::Started(latest),
+            UpdateEvent::Finished(latest),
+        ]
+    );
+    assert_eq!(convolver.update_status(first), UpdateStatus::Inactive);
+    assert_eq!(convolver.update_status(latest), UpdateStatus::Active);
+    assert_eq!(convolver.response_length(), responses[3].len());
+
+    // All updates are faded to in order until the queue is full
+    let mut convolver: CrossfadeConvolver<FFTConvolver<S>, S> = builder()
+        .pending_updates(2, PendingUpdatePolicy::QueueAll)
+        .build(&responses[0]);
+    let ids: Vec<_> = responses[1..]
+        .iter()
+        .map(|response| convolver.queue_update(response))
+        .collect();
+    assert!(matches!(
+        convolver.try_queue_update(&responses[0]),
+        Err(ConvolutionError::QueueFull)
+    ));
+    convolver.process(&input[..block_size], &mut output);
+    assert_eq!(convolver.update_status(ids[0]), UpdateStatus::FadingIn);
+    assert_eq!(convolver.update_status(ids[1]), UpdateStatus::Pending);
+    run(&mut convolver);
+    let expected: Vec<_> = ids
+        .iter()
+        .flat_map(|&id| [UpdateEvent::Started(id), UpdateEvent::Finished(id)])
+        .collect();
+    assert_eq!(events(&mut convolver), expected);
+    assert_eq!(convolver.update_status(ids[2]), UpdateStatus::Active);
+    assert_eq!(convolver.response_length(), responses[3].len());
+
+    // Updates arriving while the queue is full are dropped
+    let mut convolver: CrossfadeConvolver<FFTConvolver<S>, S> = builder()
+        .pending_updates(1, PendingUpdatePolicy::DropNew)
+        .build(&responses[0]);
+    let first = convolver.queue_update(&responses[1]);
+    let queued = convolver.queue_update(&responses[2]);
+    let dropped = convolver.queue_update(&responses[3]);
+    run(&mut convolver);
+    assert_eq!(
+        events(&mut convolver),
+        [
+            UpdateEvent::Started(first),
+            UpdateEvent::Dropped(dropped),
+            UpdateEvent::Finished(first),
+            UpdateEvent::Started(queued),
+            UpdateEvent::Finished(queued),
+        ]
+    );
+    assert_eq!(convolver.response_length(), responses[2].len());
+
+    // A reset completes the crossfade
+    let update = convolver.queue_update(&responses[0]);
+    convolver.reset();
+    assert_eq!(convolver.update_status(update), UpdateStatus::Active);
+    assert_eq!(
+        events(&mut convolver),
+        [UpdateEvent::Started(update), UpdateEvent::Finished(update)]
+    );
+
+    assert!(matches!(
+        builder()
+            .pending_updates(0, PendingUpdatePolicy::QueueAll)
+            .try_build::<FFTConvolver<S>, S>(&responses[0]),
+        Err(ConvolutionError::InvalidParameter(_))
+    ));
+}
+
+// Processes `input` in blocks and calls `update` with the position of every block
+fn process_blocks<S: Sample, C: Convolution<S>>(
+    convolver: &mut C,
+    input: &[S],
+    block_size: usize,
+    mut update: impl FnMut(&mut C, usize),
+) -> Vec<S> {
+    let mut output = vec![S::zero(); input.len()];
+    for (i, (input, output)) in input
+        .chunks(block_size)
+        .zip(output.chunks_mut(block_size))
+        .enumerate()
+    {
+        update(convolver, i * block_size);
+        convolver.process(input, output);
+    }
+    output
+}
+
+fn check_scheduled_update<S: Sample, C: Convolution<S>>(convolver: C) {
+    let block_size = 128;
+    let response: Vec<S> = generate_sinusoid(1500, 300.0, 48000.0, 0.1);
+    let input: Vec<S> = generate_sinusoid(30 * block_size, 1300.0, 48000.0, 1.0);
+
+    // Splitting the blocks by hand at the update
+    let updates = [165, 1000, 2000];
+    let mut manual = convolver.clone();
+    let mut expected = vec![S::zero(); input.len()];
+    let mut start = 0;
+    for end in updates.iter().copied().chain([input.len()]) {
+        if start > 0 {
+            manual.update(&response);
+        }
+        manual.process(&input[start..end], &mut expected[start..end]);
+        start = end;
+    }
+
+    let mut scheduled = ScheduledConvolver::new(convolver);
+    let output = process_blocks(&mut scheduled, &input, block_size, |scheduled, position| {
+        match position {
+            // Relative to the next block
+            128 => scheduled.schedule_update_in(&response, 37),
+            // Absolute and a few blocks ahead
+            256 => scheduled.schedule_update(&response, 1000),
+            1024 => scheduled.schedule_update(&response, 2000),
+            _ => {}
+        }
+        if position == 1024 {
+            assert_eq!(scheduled.scheduled_time(), Some(2000));
+        }
+    });
+    assert_eq!(scheduled.position(), input.len() as u64);
+    assert_eq!(scheduled.scheduled_time(), None);
+
+    for (output, expected) in output.iter().zip(&expected) {
+        assert!((*output - *expected).abs() < sample(1e-5));
+    }
+}
+
+fn scheduled_updates_are_sample_accurate<S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(1000, 700.0, 48000.0, 0.1);
+    check_scheduled_update(FFTConvolver::init(&response, 128, 1500));
+    check_scheduled_update(TwoStageFFTConvolver::init(&response, 128, 1500));
+    check_scheduled_update(CrossfadeConvolver::new(
+        FFTConvolver::init(&response, 128, 1500),
+        1500,
+        128,
+        300,
+    ));
+
+    // Updates scheduled in the past are applied at the start of the next block
+    let mut convolver = ScheduledConvolver::new(FFTConvolver::init(&response, 128, 1500));
+    let mut output = vec![S::zero(); 128];
+    convolver.process(&response[..128], &mut output);
+    convolver.schedule_update(&[], 10);
+    convolver.process(&response[..128], &mut output);
+    assert_eq!(convolver.response_length(), 0);
+    assert!(output.iter().all(|sample| *sample == S::zero()));
+
+    assert!(matches!(
+        convolver.try_schedule_update(&[S::zero(); 2000], 0),
+        Err(ConvolutionError::ResponseTooLong { .. })
+    ));
+}
+
+test_precisions!(
+    fft_convolver_update_is_reset,
+    test_crossfade_convolver,
+    two_stage_fft_convolver_matches_fft_convolver,
+    two_stage_fft_convolver_update_keeps_history,
+    two_stage_fft_convolver_block_sizes,
+    two_stage_fft_convolver_default_block_sizes,
+    two_stage_fft_convolver_non_power_of_two,
+    multi_stage_fft_convolver_matches_fft_convolver,
+    multi_stage_fft_convolver_default_block_sizes,
+    multi_stage_fft_convolver_update_keeps_history,
+    two_stage_fft_convolver_background_tail,
+    convolver_introspection,
+    convolver_reset_clears_history,
+    crossfade_convolver_reset_completes_crossfade,
+    exact_block_sizes,
+    shared_partitioned_response,
+    response_channel_hand_off,
+    response_channel_across_threads,
+    fft_convolver_amortised_update,
+    fft_convolver_update_keeps_history,
+    short_to_long_update_is_silent,
+    multi_stage_fft_convolver_short_to_long_update,
+    fft_convolver_update_preserves_history,
+    frequency_domain_crossfade_convolver,
+    crossfade_convolver_suspends_idle_convolver,
+    crossfade_convolver_mixers,
+    crossfade_convolver_long_response,
+    feed_keeps_history,
+    crossfade_convolver_arbitrary_process_lengths,
+    crossfade_convolver_builder,
+    scheduled_updates_are_sample_accurate,
+    crossfade_convolver_pending_updates,
+);
@@ -0,0 +1,299 @@
+// Checks that `process` and `update` neither allocate nor free memory once a convolver has been
+// constructed. The global allocator counts the calls per thread, so that tests running in
+// parallel do not interfere.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::Arc;
+
+use convolution::crossfade_convolver::{
+    CrossfadeConvolver, CrossfadeConvolverBuilder, PendingUpdatePolicy,
+};
+use convolution::fft_convolver::{FFTConvolver, TwoStageFFTConvolver, UpdateMode};
+use convolution::frequency_domain_crossfade_convolver::FrequencyDomainCrossfadeConvolver;
+use convolution::multi_stage_convolver::MultiStageFFTConvolver;
+use convolution::partitioned_response::PartitionedResponse;
+use convolution::response_channel::response_channel;
+use convolution::scheduled_convolver::ScheduledConvolver;
+use convolution::tail_worker::UnderrunPolicy;
+use convolution::{Convolution, ConvolutionError, Sample};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count() {
+    // The thread local is gone while the thread shuts down, nothing is measured then
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count();
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Number of allocations, reallocations and deallocations on the current thread during `f`
+fn count_allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+const MAX_BLOCK_SIZE: usize = 256;
+const MAX_RESPONSE_LENGTH: usize = 4000;
+// Varied lengths, including partial, single sample and empty blocks
+const BLOCK_SIZES: [usize; 8] = [256, 1, 17, 128, 255, 0, 100, 64];
+
+fn generate_sinusoid<S: Sample>(length: usize, frequency: f64, amplitude: f64) -> Vec<S> {
+    (0..length)
+        .map(|i| {
+            let phase = 2.0 * std::f64::consts::PI * frequency * i as f64 / 48000.0;
+            S::from(amplitude * phase.sin()).unwrap()
+        })
+        .collect()
+}
+
+// Drives `convolver` through updates with responses of different lengths and blocks of
+// different sizes, long enough for crossfades to complete and queued updates to start
+fn check_allocation_free<S: Sample, C: Convolution<S>>(convolver: &mut C) {
+    let responses: Vec<Vec<S>> = [MAX_RESPONSE_LENGTH, 1, 700, 0, 2500]
+        .iter()
+        .map(|&length| generate_sinusoid(length, 300.0, 0.1))
+        .collect();
+    let input: Vec<S> = generate_sinusoid(MAX_BLOCK_SIZE, 1300.0, 1.0);
+    let mut output = vec![S::zero(); MAX_BLOCK_SIZE];
+
+    let allocations = count_allocations(|| {
+        for (round, response) in responses.iter().enumerate() {
+            convolver.update(response);
+            for i in 0..40 {
+                let block_size = BLOCK_SIZES[(round + i) % BLOCK_SIZES.len()];
+                convolver.process(&input[..block_size], &mut output[..block_size]);
+            }
+            // Several updates between two blocks
+            convolver.update(&responses[(round + 1) % responses.len()]);
+            convolver.update(response);
+        }
+        convolver.reset();
+        convolver.process(&input, &mut output);
+    });
+    assert_eq!(allocations, 0);
+}
+
+fn check_convolver<C: Convolution<S>, S: Sample>() {
+    let response: Vec<S> = generate_sinusoid(1000, 700.0, 0.1);
+    let mut convolver = C::init(&response, MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+    check_allocation_free(&mut convolver);
+}
+
+#[test]
+fn fft_convolver() {
+    check_convolver::<FFTConvolver<f32>, f32>();
+    check_convolver::<FFTConvolver<f64>, f64>();
+
+    let response: Vec<f32> = generate_sinusoid(1000, 700.0, 0.1);
+    let mut convolver = FFTConvolver::init(&response, MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+    convolver.set_update_mode(UpdateMode::PreserveHistory);
+    check_allocation_free(&mut convolver);
+}
+
+#[test]
+fn fft_convolver_set_response() {
+    let responses: Vec<Vec<f32>> = [MAX_RESPONSE_LENGTH, 1, 700, 0, 2500]
+        .iter()
+        .map(|&length| generate_sinusoid(length, 300.0, 0.1))
+        .collect();
+    let mut partitioned: Vec<_> = responses
+        .iter()
+        .map(|response| {
+            Arc::new(PartitionedResponse::with_capacity(
+                response,
+                MAX_BLOCK_SIZE,
+                MAX_RESPONSE_LENGTH,
+            ))
+        })
+        .collect();
+    let input: Vec<f32> = generate_sinusoid(MAX_BLOCK_SIZE, 1300.0, 1.0);
+    let mut output = vec![0.0; MAX_BLOCK_SIZE];
+
+    for mode in [UpdateMode::DropOverlap, UpdateMode::PreserveHistory] {
+        let mut convolver = FFTConvolver::init(&responses[2], MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+        convolver.set_update_mode(mode);
+        convolver.set_update_budget(4);
+        let allocations = count_allocations(|| {
+            for (round, response) in partitioned.iter_mut().enumerate() {
+                // The swapped in response is transformed into by the following updates
+                convolver.set_response(response).unwrap();
+                convolver.process(&input, &mut output);
+                convolver.update(&responses[(round + 1) % responses.len()]);
+                convolver.begin_update(&responses[round]);
+                while convolver.update_pending() {
+                    convolver.process(&input, &mut output);
+                }
+                convolver.begin_update(&responses[(round + 2) % responses.len()]);
+                convolver.process(&input, &mut output);
+            }
+        });
+        assert_eq!(allocations, 0);
+    }
+
+    // Shared responses are rejected instead of copied
+    let mut convolver = FFTConvolver::init(&responses[2], MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+    convolver.set_update_budget(4);
+    let mut shared = partitioned[0].clone();
+    let allocations = count_allocations(|| {
+        convolver.set_response(&mut shared).unwrap();
+        assert!(matches!(
+            convolver.try_begin_update(&responses[1]),
+            Err(ConvolutionError::SharedResponse)
+        ));
+        convolver.process(&input, &mut output);
+    });
+    assert_eq!(allocations, 0);
+}
+
+#[test]
+fn response_channel_receive() {
+    let response: Vec<f32> = generate_sinusoid(1000, 700.0, 0.1);
+    let mut convolver = FFTConvolver::init(&response, MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+    let mut longer = FFTConvolver::init(&response, MAX_BLOCK_SIZE, 2 * MAX_RESPONSE_LENGTH);
+    let (mut sender, mut receiver) = response_channel(&convolver, 2);
+    let input: Vec<f32> = generate_sinusoid(MAX_BLOCK_SIZE, 1300.0, 1.0);
+    let mut output = vec![0.0; MAX_BLOCK_SIZE];
+
+    // Accepted, superseded and rejected responses are all handed back to the sender, which
+    // releases them on its own thread
+    for length in [MAX_RESPONSE_LENGTH, 1, 700, 0, 2500] {
+        sender.send_response(&generate_sinusoid(length, 300.0, 0.1));
+        sender.send_response(&generate_sinusoid(length / 2, 300.0, 0.1));
+        let allocations = count_allocations(|| {
+            assert!(receiver.receive(&mut convolver));
+            convolver.process(&input, &mut output);
+        });
+        assert_eq!(allocations, 0);
+        assert_eq!(sender.collect(), 2);
+
+        sender.send_response(&generate_sinusoid(length, 300.0, 0.1));
+        let allocations = count_allocations(|| {
+            assert!(!receiver.receive(&mut longer));
+            longer.process(&input, &mut output);
+        });
+        assert_eq!(allocations, 0);
+        assert_eq!(sender.collect(), 1);
+    }
+}
+
+#[test]
+fn two_stage_fft_convolver() {
+    check_convolver::<TwoStageFFTConvolver<f32>, f32>();
+    check_convolver::<TwoStageFFTConvolver<f64>, f64>();
+}
+
+#[test]
+fn two_stage_fft_convolver_background_tail() {
+    let responses: Vec<Vec<f32>> = [MAX_RESPONSE_LENGTH, 1, 700, 0, 2500]
+        .iter()
+        .map(|&length| generate_sinusoid(length, 300.0, 0.1))
+        .collect();
+    let input: Vec<f32> = generate_sinusoid(MAX_BLOCK_SIZE, 1300.0, 1.0);
+    let mut output = vec![0.0; MAX_BLOCK_SIZE];
+
+    for policy in [UnderrunPolicy::Wait, UnderrunPolicy::Silence] {
+        // Small tail blocks, so that the response has a 2nd tail block for the worker
+        let mut convolver =
+            TwoStageFFTConvolver::with_block_sizes(&responses[2], 64, 256, MAX_RESPONSE_LENGTH)
+                .try_with_background_tail(policy)
+                .unwrap();
+        let allocations = count_allocations(|| {
+            for (round, response) in responses.iter().enumerate() {
+                // The worker may not have picked up the previous updates yet
+                while convolver.try_update(response).is_err() {
+                    std::thread::yield_now();
+                }
+                for i in 0..40 {
+                    let block_size = BLOCK_SIZES[(round + i) % BLOCK_SIZES.len()];
+                    convolver.process(&input[..block_size], &mut output[..block_size]);
+                }
+                let _ = convolver.try_update(&responses[(round + 1) % responses.len()]);
+                let _ = convolver.try_update(response);
+            }
+            convolver.reset();
+            convolver.process(&input, &mut output);
+        });
+        assert_eq!(allocations, 0);
+    }
+}
+
+#[test]
+fn multi_stage_fft_convolver() {
+    check_convolver::<MultiStageFFTConvolver<f32>, f32>();
+    check_convolver::<MultiStageFFTConvolver<f64>, f64>();
+}
+
+#[test]
+fn crossfade_convolver() {
+    check_convolver::<CrossfadeConvolver<FFTConvolver<f32>, f32>, f32>();
+    check_convolver::<CrossfadeConvolver<FFTConvolver<f64>, f64>, f64>();
+    check_convolver::<CrossfadeConvolver<TwoStageFFTConvolver<f32>, f32>, f32>();
+
+    let response: Vec<f32> = generate_sinusoid(1000, 700.0, 0.1);
+    for policy in [PendingUpdatePolicy::QueueAll, PendingUpdatePolicy::DropNew] {
+        let mut convolver: CrossfadeConvolver<FFTConvolver<f32>, f32> =
+            CrossfadeConvolverBuilder::new(MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH)
+                .crossfade_samples(512)
+                .pending_updates(16, policy)
+                .build(&response);
+        check_allocation_free(&mut convolver);
+        assert_eq!(
+            count_allocations(|| while convolver.pop_update_event().is_some() {}),
+            0
+        );
+    }
+}
+
+#[test]
+fn frequency_domain_crossfade_convolver() {
+    check_convolver::<FrequencyDomainCrossfadeConvolver<f32>, f32>();
+    check_convolver::<FrequencyDomainCrossfadeConvolver<f64>, f64>();
+}
+
+#[test]
+fn scheduled_convolver() {
+    check_convolver::<ScheduledConvolver<FFTConvolver<f32>, f32>, f32>();
+
+    let response: Vec<f32> = generate_sinusoid(1000, 700.0, 0.1);
+    let mut convolver: ScheduledConvolver<CrossfadeConvolver<FFTConvolver<f32>, f32>, f32> =
+        ScheduledConvolver::init(&response, MAX_BLOCK_SIZE, MAX_RESPONSE_LENGTH);
+    let input: Vec<f32> = generate_sinusoid(MAX_BLOCK_SIZE, 1300.0, 1.0);
+    let mut output = vec![0.0; MAX_BLOCK_SIZE];
+    let allocations = count_allocations(|| {
+        for offset in [0, 1, 100, 255, 300] {
+            convolver.schedule_update_in(&response[..offset + 1], offset);
+            convolver.process(&input, &mut output);
+            convolver.process(&input, &mut output);
+        }
+    });
+    assert_eq!(allocations, 0);
+}